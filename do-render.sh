@@ -1,10 +1,10 @@
 #!/usr/bin/env bash
 
 mkdir -p renders
-PPMNAME=`date +%Y.%m.%d-%H.%M.%S`
+NAME=`date +%Y.%m.%d-%H.%M.%S`
 RPATH=`pwd`/renders
-PPMPATH=$RPATH/$PPMNAME.ppm
-echo "Started rendering $PPMNAME"
+PNGPATH=$RPATH/$NAME.png
+echo "Started rendering $NAME"
 
 RENDERER=biased
 #RENDERER=unbiased
@@ -16,13 +16,13 @@ SCENE=cornel_is
 time ./target/$1/rust-rt-one-weekend.exe \
   --renderer $RENDERER \
   --bounces 16 --width 400 --height 400 --samples 5000 \
-  $SCENE > $PPMPATH
-#  cornel_volumes > $PPMPATH
+  --output $PNGPATH \
+  $SCENE
+#  cornel_volumes
 #  --bounces 10 --width 384 --height 216 --samples 100 \
-#  --bounces 50 --width 384 --height 216 --samples 100 > $PPMPATH
-#  next_week_final > $PPMPATH
-#  cornel_instances > $PPMPATH
+#  --bounces 50 --width 384 --height 216 --samples 100
+#  next_week_final
+#  cornel_instances
 
-mogrify -format png $RPATH/$PPMNAME.ppm && rm $PPMPATH
-echo "Done: " $RPATH/$PPMNAME.png
-xdg-open $RPATH/$PPMNAME.png
+echo "Done: " $PNGPATH
+xdg-open $PNGPATH
@@ -14,6 +14,15 @@ impl AABB {
         AABB { min, max }
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> V3 {
+        0.5 * (self.min + self.max)
+    }
+
     pub fn hit(self, ray: &Ray, d_min: f64, d_max: f64) -> bool {
         let direction = ray.direction; // f64x3
         let start = ray.origin; // f64x3
@@ -31,7 +40,8 @@ impl AABB {
         let maxy = if d1.y < d_max { d1.y } else { d_max };
         let maxz = if d1.z < d_max { d1.z } else { d_max };
 
-        maxx > minx && maxy > miny && maxz > minz
+        // ray is inside the box where it's inside all three slabs
+        f64::min(maxx, f64::min(maxy, maxz)) > f64::max(minx, f64::max(miny, minz))
     }
 }
 
@@ -1,57 +1,487 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use rayon::prelude::*;
+
 use crate::aabb::AABB;
-use crate::hittable::{Hit, HittableList, Hittable};
+use crate::hittable::{Hit, Hittable};
 use crate::ray::Ray;
 use crate::random::random_axis;
+use crate::vec::Axis;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BvhType {
+    /// split in halves along a random axis
+    Median,
+    /// binned surface area heuristic
+    Sah,
+}
+
+impl FromStr for BvhType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median" => Result::Ok(BvhType::Median),
+            "sah" => Result::Ok(BvhType::Sah),
+            other => Result::Err(format!("Unknown variant: '{}'", other))
+        }
+    }
+}
+
+/// Relative costs of visiting a node and intersecting a primitive,
+/// split is taken only when it's expected to be cheaper than testing all primitives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SahCost {
+    pub traversal: f64,
+    pub intersection: f64,
+    /// candidate split planes per axis are bin borders, at least 2 once picked by `BvhBuilder::pick`
+    pub bins: usize,
+}
+
+impl Default for SahCost {
+    fn default() -> Self {
+        SahCost { traversal: 0.125, intersection: 1.0, bins: 16 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BvhBuilder {
+    Median,
+    Sah(SahCost),
+}
+
+impl BvhBuilder {
+    pub fn pick(bvh_type: BvhType, cost: SahCost) -> BvhBuilder {
+        match bvh_type {
+            BvhType::Median => BvhBuilder::Median,
+            // a single bin has no border to split at
+            BvhType::Sah => BvhBuilder::Sah(SahCost { bins: cost.bins.max(2), ..cost }),
+        }
+    }
+}
+
+impl Default for BvhBuilder {
+    fn default() -> Self {
+        BvhBuilder::Sah(SahCost::default())
+    }
+}
+
+/// Leaves hold up to this many primitives.
+const MAX_LEAF_SIZE: usize = 8;
+
+/// Below this depth SAH gives way to median splits, so traversal stack can't overflow.
+const MAX_SAH_DEPTH: usize = 48;
+const STACK_SIZE: usize = 96;
+
+/// Smaller nodes are built on the current thread, spawning tasks for them costs more than it saves.
+const PARALLEL_THRESHOLD: usize = 4096;
+
+type Primitive = (AABB, Box<dyn Hittable>);
 
+/// Tree as it's built, flattened into `BVH` afterwards.
+enum BuildNode {
+    Leaf(AABB, Vec<Box<dyn Hittable>>),
+    Interior(AABB, Axis, Box<BuildNode>, Box<BuildNode>),
+}
+
+impl BuildNode {
+    fn aabb(&self) -> AABB {
+        match self {
+            BuildNode::Leaf(aabb, _) | BuildNode::Interior(aabb, _, _, _) => *aabb,
+        }
+    }
+
+    fn leaf(primitives: Vec<Primitive>) -> BuildNode {
+        let aabb = bounds(&primitives);
+        BuildNode::Leaf(aabb, primitives.into_iter().map(|(_, obj)| obj).collect())
+    }
+
+    fn interior(axis: Axis, left: BuildNode, right: BuildNode) -> BuildNode {
+        BuildNode::Interior(left.aabb() + right.aabb(), axis, Box::new(left), Box::new(right))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum NodeKind {
+    /// range of `BVH::primitives`
+    Leaf { first: u32, count: u32 },
+    /// first child follows its parent, `split` tells which one is nearer
+    Interior { second: u32, split: Axis },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    aabb: AABB,
+    kind: NodeKind,
+}
+
+/// Nodes are laid out depth-first in one array and refer to primitives by index,
+/// so traversal is a loop over a small stack instead of a chain of virtual calls.
 #[derive(Debug)]
 pub struct BVH {
-    left: Box<dyn Hittable>,
-    right: Box<dyn Hittable>,
-    aabb: Option<AABB>,
+    nodes: Vec<Node>,
+    primitives: Vec<Box<dyn Hittable>>,
 }
 
 impl BVH {
-    pub(crate) fn new(objs: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
-        BVH::construct(objs)
-    }
-    fn construct(mut objs: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
-        if objs.len() == 1 { return objs.remove(0); }
-        if objs.len() <= 8 { return Box::new(HittableList::new(objs)); }
-        let axis = random_axis();
-        objs.sort_by(|a, b| {
-            // TIME!!!!
-            a.bounding_box(0.0, 1.0).unwrap().min[axis]
-                .partial_cmp(&b.bounding_box(0.0, 1.0).unwrap().min[axis])
-                .unwrap()
+    pub(crate) fn new(objs: Vec<Box<dyn Hittable>>, builder: BvhBuilder) -> Box<dyn Hittable> {
+        let primitives: Vec<Primitive> = objs.into_iter()
+            .map(|obj| (obj.bounding_box(0.0, 1.0).unwrap(), obj))
+            .collect();
+        let count = primitives.len();
+        let start = Instant::now();
+        let root = match builder {
+            BvhBuilder::Median => BVH::construct(primitives),
+            BvhBuilder::Sah(cost) => BVH::construct_sah(primitives, &cost, 0),
+        };
+        let mut bvh = BVH { nodes: vec![], primitives: Vec::with_capacity(count) };
+        bvh.flatten(root);
+        if count >= PARALLEL_THRESHOLD {
+            eprintln!("Built BVH of {} primitives in {:.2}s", count, start.elapsed().as_secs_f64());
+        }
+        Box::new(bvh)
+    }
+
+    fn construct(mut primitives: Vec<Primitive>) -> BuildNode {
+        if primitives.len() <= MAX_LEAF_SIZE { return BuildNode::leaf(primitives); }
+        let axis = *random_axis();
+        // median split only needs halves partitioned, not sorted
+        let mid = primitives.len() / 2;
+        primitives.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+            a.min[&axis].partial_cmp(&b.min[&axis]).unwrap()
         });
-        let mut a = objs;
-        let b = a.split_off(a.len() / 2);
-        let left = BVH::construct(a);
-        let right = BVH::construct(b);
-        let aabb = match (left.bounding_box(0.0, 1.0), right.bounding_box(0.0, 1.0)) {
-            (Some(l), Some(r)) => Some(l + r),
-            (None, Some(r)) => Some(r),
-            (Some(l), None) => Some(l),
-            _ => None
+        let b = primitives.split_off(mid);
+        let (left, right) = join(primitives, b, BVH::construct);
+        BuildNode::interior(axis, left, right)
+    }
+
+    fn construct_sah(mut primitives: Vec<Primitive>, cost: &SahCost, depth: usize) -> BuildNode {
+        if primitives.len() == 1 { return BuildNode::leaf(primitives); }
+        if depth >= MAX_SAH_DEPTH { return BVH::construct(primitives); }
+
+        let split = best_split(&primitives, cost);
+        let leaf_cost = primitives.len() as f64 * cost.intersection;
+        let (axis, bin) = match split {
+            Some((axis, bin, split_cost)) if primitives.len() > MAX_LEAF_SIZE || split_cost < leaf_cost => (axis, bin),
+            _ if primitives.len() <= MAX_LEAF_SIZE => return BuildNode::leaf(primitives),
+            // all centroids coincide, nothing to separate them by
+            _ => {
+                let b = primitives.split_off(primitives.len() / 2);
+                let (left, right) = join(primitives, b, |half| BVH::construct_sah(half, cost, depth + 1));
+                return BuildNode::interior(Axis::X, left, right);
+            }
+        };
+
+        let bins = Bins::new(&primitives, &axis, cost.bins);
+        let (a, b): (Vec<_>, Vec<_>) = if primitives.len() >= PARALLEL_THRESHOLD {
+            primitives.into_par_iter().partition(|(aabb, _)| bins.index(aabb) <= bin)
+        } else {
+            primitives.into_iter().partition(|(aabb, _)| bins.index(aabb) <= bin)
+        };
+        let (left, right) = join(a, b, |half| BVH::construct_sah(half, cost, depth + 1));
+        BuildNode::interior(axis, left, right)
+    }
+
+    /// Appends `node` and its subtree depth-first, returns its index.
+    fn flatten(&mut self, node: BuildNode) -> usize {
+        let idx = self.nodes.len();
+        match node {
+            BuildNode::Leaf(aabb, objs) => {
+                let first = self.primitives.len() as u32;
+                let count = objs.len() as u32;
+                self.primitives.extend(objs);
+                self.nodes.push(Node { aabb, kind: NodeKind::Leaf { first, count } });
+            }
+            BuildNode::Interior(aabb, split, left, right) => {
+                self.nodes.push(Node { aabb, kind: NodeKind::Interior { second: 0, split } });
+                self.flatten(*left);
+                let second = self.flatten(*right) as u32;
+                self.nodes[idx].kind = NodeKind::Interior { second, split };
+            }
+        }
+        idx
+    }
+}
+
+/// Builds both halves, in parallel when `a` is large enough to pay off.
+fn join<F>(a: Vec<Primitive>, b: Vec<Primitive>, construct: F) -> (BuildNode, BuildNode)
+    where F: Fn(Vec<Primitive>) -> BuildNode + Sync {
+    if a.len() + b.len() >= PARALLEL_THRESHOLD {
+        rayon::join(|| construct(a), || construct(b))
+    } else {
+        (construct(a), construct(b))
+    }
+}
+
+fn bounds(primitives: &[Primitive]) -> AABB {
+    let first = primitives[0].0;
+    if primitives.len() >= PARALLEL_THRESHOLD {
+        primitives.par_iter().map(|(aabb, _)| *aabb).reduce(|| first, |a, b| a + b)
+    } else {
+        primitives.iter().map(|(aabb, _)| *aabb).fold(first, |a, b| a + b)
+    }
+}
+
+/// Equal slices of centroid bounds along an axis.
+struct Bins<'a> {
+    axis: &'a Axis,
+    min: f64,
+    scale: f64,
+    count: usize,
+}
+
+impl<'a> Bins<'a> {
+    fn new(primitives: &[Primitive], axis: &'a Axis, count: usize) -> Bins<'a> {
+        let extend = |(min, max): (f64, f64), c: f64| (min.min(c), max.max(c));
+        let empty = (f64::INFINITY, f64::NEG_INFINITY);
+        let (min, max) = if primitives.len() >= PARALLEL_THRESHOLD {
+            primitives.par_iter()
+                .map(|(aabb, _)| aabb.centroid()[axis])
+                .fold(|| empty, extend)
+                .reduce(|| empty, |a, b| extend(extend(a, b.0), b.1))
+        } else {
+            primitives.iter()
+                .map(|(aabb, _)| aabb.centroid()[axis])
+                .fold(empty, extend)
         };
-        Box::new(BVH { left, right, aabb })
+        let scale = if max > min { count as f64 / (max - min) } else { 0.0 };
+        Bins { axis, min, scale, count }
+    }
+
+    fn index(&self, aabb: &AABB) -> usize {
+        let bin = ((aabb.centroid()[self.axis] - self.min) * self.scale) as usize;
+        bin.min(self.count - 1)
+    }
+
+    fn empty(&self) -> (Vec<Option<AABB>>, Vec<usize>) {
+        (vec![None; self.count], vec![0; self.count])
+    }
+
+    /// Bounds and number of primitives in each bin.
+    fn fill(&self, primitives: &[Primitive]) -> (Vec<Option<AABB>>, Vec<usize>) {
+        let (mut boxes, mut counts) = self.empty();
+        for (aabb, _) in primitives {
+            let idx = self.index(aabb);
+            counts[idx] += 1;
+            boxes[idx] = union(boxes[idx], Some(*aabb));
+        }
+        (boxes, counts)
+    }
+}
+
+fn merge_bins(mut a: (Vec<Option<AABB>>, Vec<usize>), b: (Vec<Option<AABB>>, Vec<usize>)) -> (Vec<Option<AABB>>, Vec<usize>) {
+    for (idx, (aabb, count)) in b.0.into_iter().zip(b.1).enumerate() {
+        a.0[idx] = union(a.0[idx], aabb);
+        a.1[idx] += count;
+    }
+    a
+}
+
+/// Axis, last bin of the left side and cost of the cheapest split.
+fn best_split(primitives: &[Primitive], cost: &SahCost) -> Option<(Axis, usize, f64)> {
+    let parent_area = bounds(primitives).surface_area();
+    let count = cost.bins;
+
+    let mut best: Option<(Axis, usize, f64)> = None;
+    for axis in Axis::xyz().iter() {
+        let bins = Bins::new(primitives, axis, count);
+        if bins.scale == 0.0 { continue; }
+
+        let (boxes, counts) = if primitives.len() >= PARALLEL_THRESHOLD {
+            primitives.par_chunks(PARALLEL_THRESHOLD)
+                .map(|chunk| bins.fill(chunk))
+                .reduce(|| bins.empty(), merge_bins)
+        } else {
+            bins.fill(primitives)
+        };
+
+        // area and count of everything right of each split, swept from the right
+        let mut right = vec![(0.0, 0usize); count];
+        let mut acc: (Option<AABB>, usize) = (None, 0);
+        for idx in (1..count).rev() {
+            acc = (union(acc.0, boxes[idx]), acc.1 + counts[idx]);
+            right[idx - 1] = (acc.0.map_or(0.0, |b| b.surface_area()), acc.1);
+        }
+        let mut left: (Option<AABB>, usize) = (None, 0);
+        for idx in 0..count - 1 {
+            left = (union(left.0, boxes[idx]), left.1 + counts[idx]);
+            let (right_area, right_count) = right[idx];
+            if left.1 == 0 || right_count == 0 { continue; }
+            let left_area = left.0.map_or(0.0, |b| b.surface_area());
+            let split_cost = cost.traversal + cost.intersection
+                * (left_area * left.1 as f64 + right_area * right_count as f64) / parent_area;
+            let better = match &best {
+                Some((_, _, best_cost)) => split_cost < *best_cost,
+                None => true,
+            };
+            if better {
+                best = Some((*axis, idx, split_cost));
+            }
+        }
+    }
+    best
+}
+
+fn union(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, None) => a,
+        (None, b) => b,
     }
 }
 
 impl Hittable for BVH {
-    fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit> {
-        if !self.aabb.unwrap().hit(ray, dist_min, dist_max) { return None; }
-        let left = self.left.hit(ray, dist_min, dist_max);
-        let right = self.right.hit(ray, dist_min, dist_max);
-        match (left, right) {
-            (Some(hit), None) => Some(hit),
-            (None, Some(hit)) => Some(hit),
-            (Some(l_hit), Some(r_hit)) => if l_hit.dist < r_hit.dist { left } else { right },
-            _ => None
+    /// Visits nearer child first and shrinks `dist_max` with every hit,
+    /// so boxes behind the closest hit so far are skipped.
+    fn hit(&self, ray: &Ray, dist_min: f64, mut dist_max: f64) -> Option<Hit<'_>> {
+        let mut closest = None;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut idx = 0;
+        loop {
+            let node = &self.nodes[idx];
+            if node.aabb.hit(ray, dist_min, dist_max) {
+                match node.kind {
+                    NodeKind::Leaf { first, count } => {
+                        for obj in &self.primitives[first as usize..(first + count) as usize] {
+                            if let Some(hit) = obj.hit(ray, dist_min, dist_max) {
+                                dist_max = hit.dist;
+                                closest = Some(hit);
+                            }
+                        }
+                    }
+                    NodeKind::Interior { second, split } => {
+                        let (near, far) = if ray.direction[&split] < 0.0 {
+                            (second as usize, idx + 1)
+                        } else {
+                            (idx + 1, second as usize)
+                        };
+                        stack[stack_len] = far as u32;
+                        stack_len += 1;
+                        idx = near;
+                        continue;
+                    }
+                }
+            }
+            if stack_len == 0 { break; }
+            stack_len -= 1;
+            idx = stack[stack_len] as usize;
         }
+        closest
     }
 
     fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
-        self.aabb
+        self.nodes.first().map(|node| node.aabb)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bvh::{BvhBuilder, BvhType, BVH, PARALLEL_THRESHOLD, SahCost};
+    use std::sync::Arc;
+
+    use crate::hittable::{AnimateOp, Hittable, HittableList, Keyframe, MovingSphere, Sphere, TranslateOp};
+    use crate::material::{Lambertian, Material};
+    use crate::random::{next_std_f64, rand_in_unit_sphere};
+    use crate::ray::Ray;
+    use crate::texture::Color;
+    use crate::vec::V3;
+
+    fn spheres(layout: &[(V3, f64)]) -> Vec<Box<dyn Hittable>> {
+        layout.iter().map(|&(center, radius)| Box::new(Sphere::new(
+            center, radius, Lambertian::new(Color(V3::ones())),
+        )) as Box<dyn Hittable>).collect()
+    }
+
+    #[test]
+    fn test_hits_match_list() {
+        // enough for top levels to be built in parallel
+        let layout: Vec<(V3, f64)> = (0..2 * PARALLEL_THRESHOLD)
+            .map(|_| (20.0 * V3::new(next_std_f64(), next_std_f64(), next_std_f64()), 0.05 + 0.3 * next_std_f64()))
+            .collect();
+        let list = HittableList::new(spheres(&layout));
+        let median = BVH::new(spheres(&layout), BvhBuilder::Median);
+        let sah = BVH::new(spheres(&layout), BvhBuilder::Sah(SahCost::default()));
+        for _ in 0..1000 {
+            let origin = V3::all(10.0) + 15.0 * rand_in_unit_sphere();
+            let ray = Ray::new(origin, rand_in_unit_sphere(), V3::ones(), 0.0, 1);
+            let expected = list.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist);
+            assert_eq!(median.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist), expected);
+            assert_eq!(sah.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist), expected);
+        }
+    }
+
+    #[test]
+    fn test_too_few_bins_are_raised() {
+        let layout: Vec<(V3, f64)> = (0..100)
+            .map(|_| (20.0 * V3::new(next_std_f64(), next_std_f64(), next_std_f64()), 0.5))
+            .collect();
+        let list = HittableList::new(spheres(&layout));
+        for bins in 0..2 {
+            let builder = BvhBuilder::pick(BvhType::Sah, SahCost { bins, ..SahCost::default() });
+            let sah = BVH::new(spheres(&layout), builder);
+            for _ in 0..100 {
+                let origin = V3::all(10.0) + 15.0 * rand_in_unit_sphere();
+                let ray = Ray::new(origin, rand_in_unit_sphere(), V3::ones(), 0.0, 1);
+                assert_eq!(sah.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist),
+                           list.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist));
+            }
+        }
+    }
+
+    #[test]
+    fn test_animation_past_unit_time_is_hit() {
+        let mut objs = spheres(&[(V3::new(0.0, 5.0, 0.0), 1.0), (V3::new(0.0, -5.0, 0.0), 1.0)]);
+        objs.push(Box::new(Sphere::new(V3::zeros(), 1.0, Lambertian::new(Color(V3::ones()))).animate()
+            .keyframe(Keyframe::new(0.0))
+            .keyframe(Keyframe::new(2.0).translate(V3::new(20.0, 0.0, 0.0)))));
+        let bvh = BVH::new(objs, BvhBuilder::default());
+        // e.g. the 47th frame at 24 fps
+        let ray = Ray::new(V3::new(19.6, 0.0, -10.0), V3::new(0.0, 0.0, 1.0), V3::ones(), 47.0 / 24.0, 1);
+        assert!(bvh.hit(&ray, 0.001, f64::MAX).is_some());
+    }
+
+    #[test]
+    fn test_moving_sphere_past_unit_time_is_hit() {
+        let material = || Box::new(Lambertian::new(Color(V3::ones()))) as Box<dyn Material>;
+        let mut objs = spheres(&[(V3::new(0.0, 5.0, 0.0), 1.0), (V3::new(0.0, -5.0, 0.0), 1.0)]);
+        objs.push(Box::new(MovingSphere::new(V3::zeros(), V3::new(10.0, 0.0, 0.0), 0.0, 1.0, 1.0, material())));
+        objs.push(Box::new(MovingSphere::new(V3::new(0.0, 0.0, 10.0), V3::new(20.0, 0.0, 10.0), 0.0, 2.0, 1.0, material())));
+        let bvh = BVH::new(objs, BvhBuilder::default());
+        let time = 47.0 / 24.0;
+        // first sphere stopped at its end position, second is still moving
+        let stopped = Ray::new(V3::new(10.0, 0.0, -10.0), V3::new(0.0, 0.0, 1.0), V3::ones(), time, 1);
+        assert_eq!(bvh.hit(&stopped, 0.001, f64::MAX).map(|hit| hit.dist), Some(9.0));
+        let moving = Ray::new(V3::new(19.6, 0.0, 0.0), V3::new(0.0, 0.0, 1.0), V3::ones(), time, 1);
+        assert!(bvh.hit(&moving, 0.001, f64::MAX).is_some());
+    }
+
+    #[test]
+    fn test_instances_match_copies() {
+        let layout: Vec<(V3, f64)> = (0..20)
+            .map(|_| (2.0 * V3::new(next_std_f64(), next_std_f64(), next_std_f64()), 0.1 + 0.5 * next_std_f64()))
+            .collect();
+        let shared: Arc<dyn Hittable> = Arc::from(BVH::new(spheres(&layout), BvhBuilder::default()));
+        let offsets: Vec<V3> = (0..50)
+            .map(|_| 20.0 * V3::new(next_std_f64(), next_std_f64(), next_std_f64()))
+            .collect();
+
+        let instances = BVH::new(offsets.iter()
+            .map(|&offset| Box::new(Arc::clone(&shared).translate(offset)) as Box<dyn Hittable>)
+            .collect(), BvhBuilder::default());
+        let copies = HittableList::new(offsets.iter()
+            .flat_map(|&offset| spheres(&layout.iter().map(|&(center, radius)| (center + offset, radius)).collect::<Vec<_>>()))
+            .collect());
+        for _ in 0..1000 {
+            let origin = V3::all(10.0) + 15.0 * rand_in_unit_sphere();
+            let ray = Ray::new(origin, rand_in_unit_sphere(), V3::ones(), 0.0, 1);
+            let expected = copies.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist);
+            let actual = instances.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => assert!((expected - actual).abs() < 1e-6),
+                (expected, actual) => assert_eq!(expected, actual),
+            }
+        }
     }
 }
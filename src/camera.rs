@@ -1,73 +1,666 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use image::RgbImage;
+
 use crate::ray::Ray;
 use crate::vec::V3;
 use crate::random;
 
-#[derive(Copy, Clone, Debug)]
-pub struct Camera {
-    lower_left: V3,
-    horizontal: V3,
-    vertical: V3,
+/// Projection of the image onto camera rays.
+pub trait Camera: Debug + Sync + Send {
+    /// Ray through point `s`, `t` of the image, both in `0..1` from the lower left corner,
+    /// `None` where projection doesn't cover the image.
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray>;
+
+    /// Same camera with another shutter.
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera>;
+
+    /// Scale of radiance reaching the image.
+    fn exposure(&self) -> f64 { 1.0 }
+}
+
+static DEFAULT_COLOR: V3 = V3::zeros();
+
+/// Shutter open over `t0..t1`, with rays distributed in time by `curve`.
+/// Rolling shutter reads rows out from top to bottom over `rolling` fraction of the interval,
+/// each row is exposed for the rest of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shutter {
+    pub t0: f32,
+    pub t1: f32,
+    pub curve: ShutterCurve,
+    pub rolling: f64,
+}
+
+impl Shutter {
+    /// Global shutter opening and closing instantly.
+    pub fn new(t0: f32, t1: f32) -> Shutter {
+        Shutter { t0, t1, curve: ShutterCurve::Box, rolling: 0.0 }
+    }
+
+    pub fn with_curve(self, curve: ShutterCurve) -> Shutter {
+        Shutter { curve, ..self }
+    }
+
+    pub fn with_rolling(self, rolling: f64) -> Shutter {
+        Shutter { rolling, ..self }
+    }
+
+    /// Time of a ray through image row `t`, `0` at the bottom.
+    fn sample(&self, t: f64) -> f32 {
+        let open = self.curve.sample(random::next_sample_1d());
+        let fraction = self.rolling * (1.0 - t) + (1.0 - self.rolling) * open;
+        interpolation::lerp(&self.t0, &self.t1, &(fraction as f32))
+    }
+}
+
+/// How much light gets in while the shutter opens and closes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShutterCurve {
+    /// fully open all the time
+    Box,
+    /// opening until the middle of the interval, closing after
+    Triangle,
+    /// piecewise linear through values evenly spaced over the interval
+    Custom(Vec<f64>),
+}
+
+impl ShutterCurve {
+    /// Maps uniform `u` to time fraction distributed by the curve.
+    fn sample(&self, u: f64) -> f64 {
+        match self {
+            ShutterCurve::Box => u,
+            ShutterCurve::Triangle => if u < 0.5 { f64::sqrt(u / 2.0) } else { 1.0 - f64::sqrt((1.0 - u) / 2.0) },
+            ShutterCurve::Custom(values) => {
+                let segments = values.len() - 1;
+                let areas: Vec<f64> = values.windows(2).map(|ab| 0.5 * (ab[0] + ab[1])).collect();
+                let mut rest = u * areas.iter().sum::<f64>();
+                for (i, &area) in areas.iter().enumerate() {
+                    if rest > area && i + 1 < segments {
+                        rest -= area;
+                        continue;
+                    }
+                    // inverse of the trapezoid area `a x + (b - a) x^2 / 2` over the segment
+                    let (a, b) = (values[i], values[i + 1]);
+                    let x = if (b - a).abs() < 1e-9 {
+                        if a > 0.0 { rest / a } else { 0.0 }
+                    } else {
+                        (f64::sqrt(f64::max(a * a + 2.0 * (b - a) * rest, 0.0)) - a) / (b - a)
+                    };
+                    return (i as f64 + x.clamp(0.0, 1.0)) / segments as f64;
+                }
+                unreachable!()
+            }
+        }
+    }
+}
+
+impl FromStr for ShutterCurve {
+    type Err = String;
+
+    /// `box`, `triangle` or comma separated values of a custom curve.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(ShutterCurve::Box),
+            "triangle" => Ok(ShutterCurve::Triangle),
+            _ => {
+                let values = s.split(',')
+                    .map(|value| value.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| format!("Unknown variant: '{}'", s))?;
+                if values.len() < 2 || values.iter().any(|&v| v < 0.0) || values.iter().all(|&v| v == 0.0) {
+                    return Err(format!("Shutter curve needs two or more non-negative values, not all zero: '{}'", s));
+                }
+                Ok(ShutterCurve::Custom(values))
+            }
+        }
+    }
+}
+
+/// Camera placement with `w` pointing back from the view direction, and its shutter.
+#[derive(Clone, Debug)]
+struct View {
     origin: V3,
     u: V3,
     v: V3,
     w: V3,
-    lens_radius: f32,
-    t0: f32,
-    t1: f32,
+    shutter: Shutter,
     ttl: i32,
 }
 
-static DEFAULT_COLOR: V3 = V3::zeros();
+impl View {
+    fn look(from: V3, at: V3, up: V3, shutter: Shutter, ttl: i32) -> View {
+        // normalized vector from origin to POI
+        let w = (from - at).unit();
+        // cross-product of upwards vector and w will give us normal to plane they are in.
+        // it's also normal to both of them, being normal to upwards direction makes it horizontal
+        let u = up.cross(w);
+        // given that we have u and w is normal to plane of viewport -- v is their cross-product
+        let v = w.cross(u);
+        View { origin: from, u, v, w, shutter, ttl }
+    }
 
-impl Camera {
+    /// Ray at random time the shutter is open for image row `t`.
+    fn ray(&self, origin: V3, direction: V3, t: f64) -> Ray {
+        Ray::new(origin, direction, DEFAULT_COLOR, self.shutter.sample(t), self.ttl)
+    }
+
+    /// Direction `theta` radians off the view direction, turned by `phi` from `u` towards `v`.
+    fn direction(&self, theta: f64, phi: f64) -> V3 {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        sin_theta * (cos_phi * self.u + sin_phi * self.v) - cos_theta * self.w
+    }
+}
+
+/// Thin lens camera, in focus at `focus_distance`.
+#[derive(Clone, Debug)]
+pub struct Perspective {
+    view: View,
+    lower_left: V3,
+    horizontal: V3,
+    vertical: V3,
+    lens_radius: f32,
+    aspect: f64,
+    bokeh: Bokeh,
+}
+
+impl Perspective {
     pub fn new_look(
         from: V3, at: V3, up: V3,
         vfov: f64, aspect: f64,
         focus_distance: f64, aperture: f32,
-        t0: f32, t1: f32,
+        shutter: Shutter,
         ttl: i32
-    ) -> Camera {
+    ) -> Perspective {
         let theta = vfov.to_radians();
         let height = (theta / 2.0).tan();
         let width = aspect * height;
-
-        // normalized vector from origin to POI
-        let w = (from - at).unit();
-        // cross-product of upwards vector and w will give us normal to plane they are in.
-        // it's also normal to both of them, being normal to upwards direction makes it horizontal
-        let u = up.cross(w);
-        // given that we have u and w is normal to plane of viewport -- v is their cross-product
-        let v = w.cross(u);
-        Camera {
+        let view = View::look(from, at, up, shutter, ttl);
+        let View { u, v, w, .. } = view.clone();
+        Perspective {
             // from origin subtract half of horizontal viewport and half of vertical viewport,
             // then offset by w; todo: research focus distance impact on values
             lower_left: from - focus_distance * ((width / 2.0) * u + (height / 2.0) * v + w),
             horizontal: focus_distance * width * u,
             vertical: focus_distance * height * v,
-            origin: from,
-            u,
-            v,
-            w,
+            view,
             lens_radius: aperture / 2.0,
-            t0,
-            t1,
-            ttl,
+            aspect,
+            bokeh: Bokeh::default(),
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let [dx, dy] = random::rand_in_unit_disc();
-        let offset = self.lens_radius as f64 * dx * self.u + self.lens_radius as f64 * dy * self.v;
-        let tmp_origin = self.origin + offset;
-        Ray::new(
+    pub fn with_bokeh(self, bokeh: Bokeh) -> Perspective {
+        Perspective { bokeh, ..self }
+    }
+}
+
+impl Camera for Perspective {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let View { origin, u, v, .. } = self.view;
+        let [dx, dy] = self.bokeh.aperture.sample();
+        if self.bokeh.cat_eye > 0.0 {
+            // lens barrel seen off axis, shifted towards the image center
+            let shift_x = self.bokeh.cat_eye * (2.0 * s - 1.0) * self.aspect;
+            let shift_y = self.bokeh.cat_eye * (2.0 * t - 1.0);
+            if (dx + shift_x).powi(2) + (dy + shift_y).powi(2) > 1.0 { return None; }
+        }
+        let offset = self.lens_radius as f64 * dx * u + self.lens_radius as f64 * dy * v;
+        let tmp_origin = origin + offset;
+        Some(self.view.ray(
             tmp_origin,
             self.lower_left
                 + ((s * self.horizontal)
                 + (t * self.vertical))
                 - tmp_origin,
-            DEFAULT_COLOR,
-            interpolation::lerp(&self.t0, &self.t1, &random::next_std_f32()),
-            self.ttl,
-        )
+            t,
+        ))
+    }
+
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera> {
+        Box::new(Perspective { view: View { shutter, ..self.view.clone() }, ..self.clone() })
+    }
+}
+
+/// Shape of the lens opening and vignetting of the lens barrel off the image center.
+#[derive(Clone, Debug, Default)]
+pub struct Bokeh {
+    pub aperture: Aperture,
+    /// shift of the barrel opening relative to aperture radius per half image height,
+    /// aperture is clipped to a cat-eye shape towards the edges, 0 disables it
+    pub cat_eye: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum Aperture {
+    #[default]
+    Disc,
+    /// regular polygon inscribed in the disc, first vertex `rotation` degrees from `u`
+    Polygon { blades: u32, rotation: f64 },
+    Image(Arc<ApertureImage>),
+}
+
+impl Aperture {
+    /// Uniformly distributed point of the aperture within the unit disc.
+    fn sample(&self) -> [f64; 2] {
+        match self {
+            Aperture::Disc => random::rand_in_unit_disc(),
+            Aperture::Polygon { blades, rotation } => {
+                let [a, b] = random::next_sample_2d();
+                // blade triangle is picked by the integer part, the fraction is left uniform
+                let blade = (a * *blades as f64).floor();
+                let a = a * *blades as f64 - blade;
+                let step = 2.0 * PI / *blades as f64;
+                let (sin0, cos0) = f64::sin_cos(rotation.to_radians() + blade * step);
+                let (sin1, cos1) = f64::sin_cos(rotation.to_radians() + (blade + 1.0) * step);
+                let r = a.sqrt();
+                [r * ((1.0 - b) * cos0 + b * cos1), r * ((1.0 - b) * sin0 + b * sin1)]
+            }
+            Aperture::Image(image) => image.sample(random::next_sample_2d()),
+        }
+    }
+}
+
+/// Aperture mask sampled in proportion to pixel brightness, fit into the unit disc.
+#[derive(Debug)]
+pub struct ApertureImage {
+    width: u32,
+    height: u32,
+    /// running sum of pixel weights, normalized to end at 1
+    cdf: Vec<f64>,
+}
+
+impl ApertureImage {
+    /// `None` for black images.
+    pub fn new(image: &RgbImage) -> Option<ApertureImage> {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = image.pixels()
+            .map(|pixel| {
+                total += pixel.0.iter().map(|&c| c as f64).sum::<f64>();
+                total
+            })
+            .collect();
+        if total <= 0.0 { return None; }
+        cdf.iter_mut().for_each(|c| *c /= total);
+        Some(ApertureImage { width: image.width(), height: image.height(), cdf })
+    }
+
+    fn sample(&self, [a, b]: [f64; 2]) -> [f64; 2] {
+        let idx = self.cdf.partition_point(|&c| c <= a).min(self.cdf.len() - 1);
+        let low = if idx == 0 { 0.0 } else { self.cdf[idx - 1] };
+        // position within the pixel comes from the rest of `a` and from `b`
+        let dx = (a - low) / (self.cdf[idx] - low);
+        let (x, y) = ((idx as u32 % self.width) as f64 + dx, (idx as u32 / self.width) as f64 + b);
+        let size = self.width.max(self.height) as f64;
+        [(2.0 * x - self.width as f64) / size, (self.height as f64 - 2.0 * y) / size]
+    }
+}
+
+/// Parallel rays through a `height` tall window centered at `from`.
+#[derive(Clone, Debug)]
+pub struct Orthographic {
+    view: View,
+    horizontal: V3,
+    vertical: V3,
+}
+
+impl Orthographic {
+    pub fn new_look(from: V3, at: V3, up: V3, height: f64, aspect: f64, shutter: Shutter, ttl: i32) -> Orthographic {
+        let view = View::look(from, at, up, shutter, ttl);
+        Orthographic {
+            horizontal: aspect * height * view.u,
+            vertical: height * view.v,
+            view,
+        }
+    }
+}
+
+impl Camera for Orthographic {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let origin = self.view.origin + (s - 0.5) * self.horizontal + (t - 0.5) * self.vertical;
+        Some(self.view.ray(origin, -self.view.w, t))
+    }
+
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera> {
+        Box::new(Orthographic { view: View { shutter, ..self.view.clone() }, ..self.clone() })
+    }
+}
+
+/// Equidistant fisheye, angle off the view direction grows linearly with distance from the image center
+/// up to `fov / 2` degrees at the top and bottom edges. Pixels past 180 degrees stay black.
+#[derive(Clone, Debug)]
+pub struct Fisheye {
+    view: View,
+    /// radians per image height
+    fov: f64,
+    aspect: f64,
+}
+
+impl Fisheye {
+    pub fn new_look(from: V3, at: V3, up: V3, fov: f64, aspect: f64, shutter: Shutter, ttl: i32) -> Fisheye {
+        Fisheye { view: View::look(from, at, up, shutter, ttl), fov: fov.to_radians(), aspect }
+    }
+}
+
+impl Camera for Fisheye {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let (x, y) = ((s - 0.5) * self.aspect, t - 0.5);
+        let theta = f64::sqrt(x * x + y * y) * self.fov;
+        if theta > PI { return None; }
+        Some(self.view.ray(self.view.origin, self.view.direction(theta, y.atan2(x)), t))
+    }
+
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera> {
+        Box::new(Fisheye { view: View { shutter, ..self.view.clone() }, ..self.clone() })
+    }
+}
+
+/// Full sphere around `from`, longitude along the image width with the view direction in the middle,
+/// latitude along the height. Images are expected to be twice as wide as high.
+#[derive(Clone, Debug)]
+pub struct Equirectangular {
+    view: View,
+}
+
+impl Equirectangular {
+    pub fn new_look(from: V3, at: V3, up: V3, shutter: Shutter, ttl: i32) -> Equirectangular {
+        Equirectangular { view: View::look(from, at, up, shutter, ttl) }
+    }
+}
+
+impl Camera for Equirectangular {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let longitude = 2.0 * PI * (s - 0.5);
+        let latitude = PI * (t - 0.5);
+        let View { u, v, w, .. } = self.view;
+        let direction = latitude.cos() * (longitude.sin() * u - longitude.cos() * w) + latitude.sin() * v;
+        Some(self.view.ray(self.view.origin, direction, t))
+    }
+
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera> {
+        Box::new(Equirectangular { view: View { shutter, ..self.view.clone() } })
+    }
+}
+
+/// Kind of camera built from keyframes of a `CameraPath`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// window as tall as the perspective view at `focus_distance`
+    Orthographic,
+    /// `vfov` is the angle across the image height
+    Fisheye,
+    /// `vfov`, `aperture` and `focus_distance` are ignored
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn camera(self, key: &CameraKeyframe, bokeh: &Bokeh, aspect: f64, shutter: Shutter, ttl: i32) -> Box<dyn Camera> {
+        match self {
+            Projection::Perspective => Box::new(Perspective::new_look(
+                key.from, key.at, key.up, key.vfov, aspect, key.focus_distance, key.aperture, shutter, ttl,
+            ).with_bokeh(bokeh.clone())),
+            Projection::Orthographic => {
+                let height = key.focus_distance * (key.vfov.to_radians() / 2.0).tan();
+                Box::new(Orthographic::new_look(key.from, key.at, key.up, height, aspect, shutter, ttl))
+            }
+            Projection::Fisheye => Box::new(Fisheye::new_look(key.from, key.at, key.up, key.vfov, aspect, shutter, ttl)),
+            Projection::Equirectangular => Box::new(Equirectangular::new_look(key.from, key.at, key.up, shutter, ttl)),
+        }
+    }
+}
+
+/// Photographic camera settings, they replace `vfov` and `aperture` of keyframes,
+/// shutter interval and exposure of the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lens {
+    /// millimeters
+    pub focal_length: f64,
+    pub f_stop: f64,
+    /// seconds
+    pub shutter: f32,
+    pub iso: f64,
+    /// millimeters, 24 for full frame
+    pub sensor_height: f64,
+    /// meters per scene unit
+    pub unit: f64,
+}
+
+impl Lens {
+    /// Field of view as `vfov` of `Perspective`, which spans `tan(vfov / 2)` at unit distance.
+    pub fn vfov(&self) -> f64 {
+        2.0 * f64::atan(self.sensor_height / self.focal_length).to_degrees()
+    }
+
+    /// Entrance pupil diameter in scene units.
+    pub fn aperture(&self) -> f64 {
+        self.focal_length / self.f_stop / 1000.0 / self.unit
+    }
+
+    /// 1 at EV 15 (ISO 100), so radiance of 1 is exposed as bright daylight.
+    pub fn exposure(&self) -> f64 {
+        let ev100 = f64::log2(self.f_stop * self.f_stop / self.shutter as f64 * 100.0 / self.iso);
+        f64::exp2(15.0 - ev100)
+    }
+}
+
+/// Camera with radiance scaled by `exposure`.
+#[derive(Debug)]
+pub struct Exposed {
+    camera: Box<dyn Camera>,
+    exposure: f64,
+}
+
+impl Camera for Exposed {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        self.camera.get_ray(s, t)
+    }
+
+    fn with_shutter(&self, shutter: Shutter) -> Box<dyn Camera> {
+        Box::new(Exposed { camera: self.camera.with_shutter(shutter), exposure: self.exposure })
+    }
+
+    fn exposure(&self) -> f64 {
+        self.exposure * self.camera.exposure()
+    }
+}
+
+/// Camera placement at `time`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub from: V3,
+    pub at: V3,
+    pub up: V3,
+    pub vfov: f64,
+    pub aperture: f32,
+    pub focus_distance: f64,
+}
+
+/// Keyframed camera, `from`, `at` and `up` follow Catmull-Rom splines through keyframes,
+/// lens parameters are interpolated linearly. Held still before the first and after the last keyframe.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    /// sorted by time, never empty
+    keyframes: Vec<CameraKeyframe>,
+    projection: Projection,
+    bokeh: Bokeh,
+    lens: Option<Lens>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<CameraKeyframe>) -> CameraPath {
+        assert!(!keyframes.is_empty(), "camera path needs a keyframe");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        CameraPath { keyframes, projection: Projection::default(), bokeh: Bokeh::default(), lens: None }
+    }
+
+    pub fn with_projection(self, projection: Projection) -> CameraPath {
+        CameraPath { projection, ..self }
+    }
+
+    pub fn with_bokeh(self, bokeh: Bokeh) -> CameraPath {
+        CameraPath { bokeh, ..self }
+    }
+
+    pub fn with_lens(self, lens: Option<Lens>) -> CameraPath {
+        CameraPath { lens, ..self }
+    }
+
+    pub fn at(&self, time: f32) -> CameraKeyframe {
+        let keys = &self.keyframes;
+        let idx = keys.partition_point(|k| k.time <= time);
+        if idx == 0 { return keys[0]; }
+        if idx == keys.len() { return keys[idx - 1]; }
+
+        let (k1, k2) = (&keys[idx - 1], &keys[idx]);
+        // missing neighbours are replaced by the ends of the segment, which flattens the tangent there
+        let k0 = if idx >= 2 { &keys[idx - 2] } else { k1 };
+        let k3 = keys.get(idx + 1).unwrap_or(k2);
+        let t = ((time - k1.time) / (k2.time - k1.time)) as f64;
+        let spline = |p: fn(&CameraKeyframe) -> V3| hermite(
+            [p(k0), p(k1), p(k2), p(k3)],
+            [k0.time, k1.time, k2.time, k3.time],
+            t,
+        );
+        CameraKeyframe {
+            time,
+            from: spline(|k| k.from),
+            at: spline(|k| k.at),
+            up: spline(|k| k.up),
+            vfov: k1.vfov + t * (k2.vfov - k1.vfov),
+            aperture: k1.aperture + t as f32 * (k2.aperture - k1.aperture),
+            focus_distance: k1.focus_distance + t * (k2.focus_distance - k1.focus_distance),
+        }
+    }
+
+    /// Camera placed where it is in the middle of the shutter interval.
+    /// With `lens` shutter is open for its `shutter` time from `t0` instead.
+    pub fn camera(&self, aspect: f64, shutter: Shutter, ttl: i32) -> Box<dyn Camera> {
+        let lens = match self.lens {
+            Some(lens) => lens,
+            None => return self.projection.camera(&self.at(0.5 * (shutter.t0 + shutter.t1)), &self.bokeh, aspect, shutter, ttl),
+        };
+        let shutter = Shutter { t1: shutter.t0 + lens.shutter, ..shutter };
+        let key = CameraKeyframe {
+            vfov: lens.vfov(),
+            aperture: lens.aperture() as f32,
+            ..self.at(0.5 * (shutter.t0 + shutter.t1))
+        };
+        Box::new(Exposed {
+            camera: self.projection.camera(&key, &self.bokeh, aspect, shutter, ttl),
+            exposure: lens.exposure(),
+        })
+    }
+}
+
+/// Cubic Hermite segment between `points[1]` and `points[2]` with Catmull-Rom tangents
+/// scaled for uneven spacing of keyframe `times`.
+fn hermite(points: [V3; 4], times: [f32; 4], t: f64) -> V3 {
+    let [p0, p1, p2, p3] = points;
+    let [t0, t1, t2, t3] = times.map(|t| t as f64);
+    let span = t2 - t1;
+    let tangent = |a: V3, b: V3, dt: f64| if dt > 0.0 { (span / dt) * (b - a) } else { V3::zeros() };
+    let m1 = tangent(p0, p2, t2 - t0);
+    let m2 = tangent(p1, p3, t3 - t1);
+
+    let (sqr, cube) = (t * t, t * t * t);
+    (2.0 * cube - 3.0 * sqr + 1.0) * p1
+        + (cube - 2.0 * sqr + t) * m1
+        + (-2.0 * cube + 3.0 * sqr) * p2
+        + (cube - sqr) * m2
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::camera::{Aperture, ApertureImage, Camera, CameraKeyframe, CameraPath, Equirectangular, Fisheye, Lens, Orthographic, Shutter, ShutterCurve};
+    use crate::vec::V3;
+
+    fn keyframe(time: f32, from: V3) -> CameraKeyframe {
+        CameraKeyframe { time, from, at: V3::zeros(), up: V3::new(0.0, 1.0, 0.0), vfov: 40.0, aperture: 0.0, focus_distance: 10.0 }
+    }
+
+    #[test]
+    fn test_path_passes_keyframes() {
+        let points = [V3::new(10.0, 0.0, 0.0), V3::new(0.0, 0.0, 10.0), V3::new(-10.0, 0.0, 0.0), V3::new(0.0, 0.0, -10.0)];
+        let path = CameraPath::new(points.iter().enumerate()
+            .map(|(i, &p)| keyframe(i as f32, p))
+            .collect());
+        for (i, &p) in points.iter().enumerate() {
+            assert!((path.at(i as f32).from - p).length() < 1e-9);
+        }
+        assert_eq!(path.at(-1.0).from, points[0]);
+        assert_eq!(path.at(5.0).from, points[3]);
+        // middle segment bulges out of its chord, towards the circle through keyframes
+        assert!(path.at(1.5).from.length() > V3::new(-5.0, 0.0, 5.0).length());
+    }
+
+    #[test]
+    fn test_projections_look_at_target() {
+        let (from, at, up) = (V3::new(1.0, 2.0, 3.0), V3::new(-2.0, 0.0, 1.0), V3::new(0.0, 1.0, 0.0));
+        let forward = (at - from).unit();
+        let cameras: [Box<dyn Camera>; 3] = [
+            Box::new(Orthographic::new_look(from, at, up, 2.0, 1.5, Shutter::new(0.0, 1.0), 10)),
+            Box::new(Fisheye::new_look(from, at, up, 360.0, 1.0, Shutter::new(0.0, 1.0), 10)),
+            Box::new(Equirectangular::new_look(from, at, up, Shutter::new(0.0, 1.0), 10)),
+        ];
+        for camera in cameras.iter() {
+            let ray = camera.get_ray(0.5, 0.5).unwrap();
+            assert!((ray.origin - from).length() < 1e-9);
+            assert!((ray.direction.unit() - forward).length() < 1e-9);
+        }
+        // orthographic rays are parallel, fisheye covers the full sphere within the image circle
+        assert!((cameras[0].get_ray(0.0, 1.0).unwrap().direction.unit() - forward).length() < 1e-9);
+        assert!((cameras[1].get_ray(0.5, 1.0).unwrap().direction.unit() + forward).length() < 1e-9);
+        assert!(cameras[1].get_ray(0.0, 0.0).is_none());
+        // left edge of the panorama looks backwards
+        assert!((cameras[2].get_ray(0.0, 0.5).unwrap().direction.unit() + forward).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_aperture_shapes() {
+        let hexagon = Aperture::Polygon { blades: 6, rotation: 30.0 };
+        // only the left half of the mask is open
+        let mask = RgbImage::from_fn(8, 4, |x, _| if x < 4 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) });
+        let half = Aperture::Image(Arc::new(ApertureImage::new(&mask).unwrap()));
+        for _ in 0..1000 {
+            let [x, y] = hexagon.sample();
+            // within flat sides at 0 and 180 degrees
+            assert!(x.abs() <= f64::cos(PI / 6.0) + 1e-9);
+            assert!(x * x + y * y <= 1.0 + 1e-9);
+            let [x, y] = half.sample();
+            assert!((-1.0..=0.0).contains(&x) && (-0.5..=0.5).contains(&y));
+        }
+        assert!(ApertureImage::new(&RgbImage::new(4, 4)).is_none());
+
+        let sunny_16 = Lens { focal_length: 50.0, f_stop: 16.0, shutter: 1.0 / 128.0, iso: 100.0, sensor_height: 24.0, unit: 1.0 };
+        assert!((sunny_16.exposure() - 1.0).abs() < 1e-9);
+        assert!((Lens { iso: 400.0, ..sunny_16 }.exposure() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_shutter_curves() {
+        let mean = |curve: &ShutterCurve| (0..1000).map(|i| curve.sample((i as f64 + 0.5) / 1000.0)).sum::<f64>() / 1000.0;
+        assert!((mean(&ShutterCurve::Box) - 0.5).abs() < 1e-3);
+        assert!((mean(&ShutterCurve::Triangle) - 0.5).abs() < 1e-3);
+        assert!((mean(&"0, 1".parse().unwrap()) - 2.0 / 3.0).abs() < 1e-3);
+        // closed for the first half, then opening linearly
+        assert!((mean(&"0,0,1".parse().unwrap()) - 5.0 / 6.0).abs() < 1e-3);
+        assert!("0,0".parse::<ShutterCurve>().is_err());
+        assert!("round".parse::<ShutterCurve>().is_err());
+
+        let rolling = Shutter::new(1.0, 2.0).with_rolling(1.0);
+        assert_eq!(rolling.sample(1.0), 1.0);
+        assert_eq!(rolling.sample(0.25), 1.75);
     }
 }
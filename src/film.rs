@@ -0,0 +1,364 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::sequence::SequenceType;
+use crate::vec::V3;
+
+/// Rectangular block of pixels, `y` counts from the top row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile {
+    pub x: Range<u32>,
+    pub y: Range<u32>,
+}
+
+impl Tile {
+    pub fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = vec![];
+        for y in (0..height).step_by(size as usize) {
+            for x in (0..width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x: x..u32::min(x + size, width),
+                    y: y..u32::min(y + size, height),
+                });
+            }
+        }
+        tiles
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item=(u32, u32)> + '_ {
+        self.y.clone().flat_map(move |y| self.x.clone().map(move |x| (x, y)))
+    }
+}
+
+/// Running estimate of a single pixel: radiance sum and luminance sum of squares,
+/// enough to track the variance of the mean without keeping samples.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Estimate {
+    pub sum: V3,
+    pub sum_sq: f64,
+    pub samples: u32,
+}
+
+impl Estimate {
+    pub const fn empty() -> Estimate {
+        Estimate { sum: V3::zeros(), sum_sq: 0.0, samples: 0 }
+    }
+
+    pub fn add_sample(&mut self, color: V3) {
+        let y = color.luminance();
+        self.sum += color;
+        self.sum_sq += y * y;
+        self.samples += 1;
+    }
+
+    pub fn merge(&mut self, other: &Estimate) {
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.samples += other.samples;
+    }
+
+    pub fn mean(&self) -> V3 {
+        if self.samples > 0 { self.sum / self.samples as f64 } else { V3::zeros() }
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself.
+    /// Mean is floored, so that near-black pixels don't keep sampling forever.
+    pub fn relative_error(&self) -> f64 {
+        if self.samples < 2 { return f64::INFINITY; }
+        let n = self.samples as f64;
+        let mean = self.sum.luminance() / n;
+        let variance = f64::max(0.0, (self.sum_sq - n * mean * mean) / (n - 1.0));
+        f64::sqrt(variance / n) / f64::max(mean, 0.01)
+    }
+}
+
+/// Seed and sequence samples of a film were rendered with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Source {
+    pub seed: u64,
+    pub sequence: SequenceType,
+}
+
+/// Accumulated radiance estimates per pixel.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    /// Every render that added samples, resumed ones included
+    pub sources: Vec<Source>,
+    estimates: Vec<Estimate>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let size = (width * height) as usize;
+        Film { width, height, sources: vec![], estimates: vec![Estimate::empty(); size] }
+    }
+
+    pub fn add_source(&mut self, source: Source) {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+    }
+
+    #[inline]
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn estimate(&self, x: u32, y: u32) -> &Estimate {
+        &self.estimates[self.index(x, y)]
+    }
+
+    /// Merges per-pixel `estimates` of `tile`, in `Tile::pixels` order.
+    pub fn add_tile(&mut self, tile: &Tile, estimates: &[Estimate]) {
+        debug_assert!(tile.x.end <= self.width && tile.y.end <= self.height);
+        for ((x, y), estimate) in tile.pixels().zip(estimates) {
+            let idx = self.index(x, y);
+            self.estimates[idx].merge(estimate);
+        }
+    }
+
+    /// Mean radiance, row-major, top row first.
+    pub fn pixels(&self) -> Vec<V3> {
+        self.estimates.iter().map(Estimate::mean).collect()
+    }
+
+    /// Writes raw estimates, so that rendering can continue from them.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(FILM_MAGIC)?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&(self.sources.len() as u32).to_le_bytes())?;
+        for source in &self.sources {
+            out.write_all(&source.seed.to_le_bytes())?;
+            out.write_all(&[sequence_id(source.sequence)])?;
+        }
+        for estimate in &self.estimates {
+            for value in &[estimate.sum.x, estimate.sum.y, estimate.sum.z, estimate.sum_sq] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&estimate.samples.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Film> {
+        let file = File::open(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        let len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != FILM_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("'{}' is not a checkpoint", path.display())));
+        }
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let mut sources = vec![];
+        for _ in 0..read_u32(&mut input)? {
+            let seed = read_u64(&mut input)?;
+            let mut id = [0u8];
+            input.read_exact(&mut id)?;
+            let sequence = sequence_from_id(id[0]).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData, format!("'{}' has unknown sequence {}", path.display(), id[0])))?;
+            sources.push(Source { seed, sequence });
+        }
+        // check the header against the file before allocating anything it asks for
+        let header = (FILM_MAGIC.len() + 12 + sources.len() * SOURCE_BYTES) as u64;
+        let payload = width.checked_mul(height).map(|pixels| pixels as u64 * ESTIMATE_BYTES);
+        if payload.map(|payload| header + payload) != Some(len) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("'{}' doesn't match its {}x{} header", path.display(), width, height)));
+        }
+        let mut film = Film::new(width, height);
+        film.sources = sources;
+        for estimate in film.estimates.iter_mut() {
+            let x = read_f64(&mut input)?;
+            let y = read_f64(&mut input)?;
+            let z = read_f64(&mut input)?;
+            *estimate = Estimate {
+                sum: V3::new(x, y, z),
+                sum_sq: read_f64(&mut input)?,
+                samples: read_u32(&mut input)?,
+            };
+        }
+        Ok(film)
+    }
+
+    /// Samples spent per pixel, scaled so that the busiest pixel is white.
+    pub fn heatmap(&self) -> Vec<V3> {
+        let max_samples = self.estimates.iter().map(|e| e.samples).max().unwrap_or(0).max(1);
+        self.estimates.iter()
+            .map(|estimate| V3::all(estimate.samples as f64 / max_samples as f64))
+            .collect()
+    }
+
+    /// Adds samples of an independent render of the same image,
+    /// pixel means end up weighted by their sample counts.
+    /// Renders sharing a seed aren't independent, whatever their sequence:
+    /// dimensions the sequence doesn't cover come from the seed alone.
+    pub fn merge(&mut self, other: &Film) -> Result<(), String> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(format!("Can't merge {}x{} film into {}x{}",
+                               other.width, other.height, self.width, self.height));
+        }
+        if let Some(source) = other.sources.iter().find(|other| self.sources.iter().any(|s| s.seed == other.seed)) {
+            return Err(format!("Can't merge films both rendered with seed {}", source.seed));
+        }
+        other.sources.iter().for_each(|source| self.add_source(*source));
+        self.estimates.iter_mut()
+            .zip(&other.estimates)
+            .for_each(|(estimate, other)| estimate.merge(other));
+        Ok(())
+    }
+}
+
+const FILM_MAGIC: &[u8; 8] = b"RTFILM2\n";
+/// Serialized sizes of a `Source` and an `Estimate`.
+const SOURCE_BYTES: usize = 8 + 1;
+const ESTIMATE_BYTES: u64 = 4 * 8 + 4;
+
+fn sequence_id(sequence: SequenceType) -> u8 {
+    match sequence {
+        SequenceType::Independent => 0,
+        SequenceType::Stratified => 1,
+        SequenceType::Halton => 2,
+        SequenceType::Sobol => 3,
+    }
+}
+
+fn sequence_from_id(id: u8) -> Option<SequenceType> {
+    match id {
+        0 => Some(SequenceType::Independent),
+        1 => Some(SequenceType::Stratified),
+        2 => Some(SequenceType::Halton),
+        3 => Some(SequenceType::Sobol),
+        _ => None,
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::film::{Estimate, Film, Source, Tile};
+    use crate::sequence::SequenceType;
+    use crate::vec::V3;
+
+    #[test]
+    fn test_tiles_cover_image_once() {
+        let (width, height) = (100, 70);
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in Tile::split(width, height, 32) {
+            tile.pixels().for_each(|(x, y)| covered[(y * width + x) as usize] += 1);
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_relative_error_of_constant_is_zero() {
+        let mut estimate = Estimate::empty();
+        (0..16).for_each(|_| estimate.add_sample(V3::new(0.2, 0.5, 0.7)));
+        assert!(estimate.relative_error() < 1e-6);
+
+        let mut noisy = Estimate::empty();
+        (0..16).for_each(|i| noisy.add_sample(V3::all((i % 2) as f64)));
+        assert!(noisy.relative_error() > 0.1);
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let mut film = Film::new(3, 2);
+        let tile = Tile { x: 1..3, y: 0..2 };
+        let mut estimate = Estimate::empty();
+        estimate.add_sample(V3::new(0.5, 1.5, 2.5));
+        estimate.add_sample(V3::new(0.1, 0.2, 0.3));
+        film.add_tile(&tile, &[estimate; 4]);
+        film.add_source(Source { seed: 7, sequence: SequenceType::Sobol });
+
+        let path = std::env::temp_dir().join("rt_film_roundtrip.bin");
+        film.save(&path).unwrap();
+        let loaded = Film::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.sources, film.sources);
+        for (x, y) in (Tile { x: 0..3, y: 0..2 }).pixels() {
+            assert_eq!(loaded.estimate(x, y), film.estimate(x, y));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_checkpoint_size_is_rejected() {
+        let path = std::env::temp_dir().join("rt_film_corrupt.bin");
+        Film::new(3, 2).save(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        // width and height multiplying past u32
+        bytes[8..16].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, &bytes).unwrap();
+        let huge = Film::load(&path).unwrap_err();
+
+        Film::new(3, 2).save(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let truncated = Film::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(huge.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(truncated.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_merge_weights_by_samples() {
+        let tile = Tile { x: 0..1, y: 0..1 };
+        let mut a = Film::new(1, 1);
+        let mut one = Estimate::empty();
+        one.add_sample(V3::all(1.0));
+        a.add_tile(&tile, &[one]);
+
+        let mut b = Film::new(1, 1);
+        let mut three = Estimate::empty();
+        (0..3).for_each(|_| three.add_sample(V3::all(0.0)));
+        b.add_tile(&tile, &[three]);
+
+        a.merge(&b).unwrap();
+        assert_eq!(a.pixels(), vec![V3::all(0.25)]);
+        assert!(a.merge(&Film::new(2, 1)).is_err());
+    }
+
+    #[test]
+    fn test_merge_rejects_same_seed() {
+        let mut a = Film::new(1, 1);
+        a.add_source(Source { seed: 1, sequence: SequenceType::Independent });
+        let mut b = Film::new(1, 1);
+        b.add_source(Source { seed: 2, sequence: SequenceType::Independent });
+        a.merge(&b).unwrap();
+        assert_eq!(a.sources.len(), 2);
+
+        let mut c = Film::new(1, 1);
+        c.add_source(Source { seed: 2, sequence: SequenceType::Sobol });
+        assert!(a.merge(&c).is_err());
+    }
+}
@@ -3,6 +3,7 @@ use std::f64::consts::PI;
 use itertools::Itertools;
 
 use super::{AABB, Hit, Hittable, Ray, V3};
+use crate::matrix::{M4, Quat};
 
 pub trait FlipNormalsOp<I, O>{
     fn flip_normals(self) -> O;
@@ -15,6 +16,11 @@ pub trait RotateYOp<I> {
     fn rotate_y(self, angle: f64) -> RotateY<I>;
 }
 
+pub trait TransformOp<I> {
+    /// Starts with identity, builder methods of `Transform` compose the rest.
+    fn transform(self) -> Transform<I>;
+}
+
 
 #[derive(Debug, Clone)]
 pub struct FlipNormals<T>(T);
@@ -188,9 +194,268 @@ impl<I> RotateY<I> {
     }
 }
 
+/// Affine transform, ray is moved into target's space without normalizing direction,
+/// so hit distances are the same in both spaces.
+#[derive(Debug, Clone)]
+pub struct Transform<T> {
+    target: T,
+    /// target's space to world
+    matrix: M4,
+    /// world to target's space
+    inverse: M4,
+}
+
+impl<I: Hittable + Sized> TransformOp<I> for I {
+    fn transform(self) -> Transform<I> {
+        Transform { target: self, matrix: M4::identity(), inverse: M4::identity() }
+    }
+}
+
+impl<T> Transform<T> {
+    /// Applies `matrix` after the current transform, `None` if it's singular.
+    pub fn then(self, matrix: M4) -> Option<Transform<T>> {
+        let inverse = matrix.inverse()?;
+        Some(Transform { matrix: matrix * self.matrix, inverse: self.inverse * inverse, ..self })
+    }
+
+    pub fn translate(self, offset: V3) -> Transform<T> {
+        Transform {
+            matrix: M4::translation(offset) * self.matrix,
+            inverse: self.inverse * M4::translation(-offset),
+            ..self
+        }
+    }
+
+    /// Zero factors make the transform singular.
+    pub fn scale(self, factors: V3) -> Transform<T> {
+        Transform {
+            matrix: M4::scaling(factors) * self.matrix,
+            inverse: self.inverse * M4::scaling(V3::ones() / factors),
+            ..self
+        }
+    }
+
+    pub fn rotate(self, axis: V3, angle: f64) -> Transform<T> {
+        Transform {
+            matrix: M4::rotation(axis, angle) * self.matrix,
+            inverse: self.inverse * M4::rotation(axis, -angle),
+            ..self
+        }
+    }
+
+    pub fn rotate_x(self, angle: f64) -> Transform<T> {
+        self.rotate(V3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub fn rotate_y(self, angle: f64) -> Transform<T> {
+        self.rotate(V3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub fn rotate_z(self, angle: f64) -> Transform<T> {
+        self.rotate(V3::new(0.0, 0.0, 1.0), angle)
+    }
+}
+
+impl<T: Hittable> Hittable for Transform<T> {
+    fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit<'_>> {
+        hit_transformed(&self.target, &self.matrix, &self.inverse, ray, dist_min, dist_max)
+    }
+
+    fn bounding_box(&self, t_min: f32, t_max: f32) -> Option<AABB> {
+        self.target.bounding_box(t_min, t_max)
+            .map(|aabb| bounds(corners(&aabb).iter().map(|&p| self.matrix.transform_point(p))))
+    }
+
+    /// Target's density of the direction in its space times the Jacobian of mapping
+    /// world directions to it, `|det A| / |A w|^3` for the linear part `A` of the inverse.
+    fn pdf_value(&self, origin: &V3, direction: &V3, hit: &Hit) -> f64 {
+        let world = direction.unit();
+        let local = self.inverse.transform_vector(world);
+        let stretch = local.length();
+        let local_hit = Hit {
+            dist: hit.dist * direction.length() * stretch,
+            point: self.inverse.transform_point(hit.point),
+            normal: self.matrix.transform_transposed(hit.normal).unit(),
+            ..*hit
+        };
+        let origin = self.inverse.transform_point(*origin);
+        let jacobian = self.inverse.determinant().abs() / (stretch * stretch * stretch);
+        self.target.pdf_value(&origin, &(local / stretch), &local_hit) * jacobian
+    }
+
+    fn random(&self, origin: &V3) -> V3 {
+        self.matrix.transform_vector(self.target.random(&self.inverse.transform_point(*origin)))
+    }
+}
+
+fn hit_transformed<'a, T: Hittable>(target: &'a T, matrix: &M4, inverse: &M4,
+                                    ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit<'a>> {
+    let local = Ray {
+        origin: inverse.transform_point(ray.origin),
+        direction: inverse.transform_vector(ray.direction),
+        ..*ray
+    };
+    target.hit(&local, dist_min, dist_max)
+        .map(|hit| Hit {
+            point: matrix.transform_point(hit.point),
+            normal: inverse.transform_transposed(hit.normal).unit(),
+            ..hit
+        })
+}
+
+fn corners(aabb: &AABB) -> [V3; 8] {
+    let mut corners = [aabb.min; 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        *corner = V3::new(
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        );
+    }
+    corners
+}
+
+fn bounds(points: impl Iterator<Item=V3>) -> AABB {
+    points.fold(AABB::new(V3::all(f64::INFINITY), V3::all(f64::NEG_INFINITY)), |aabb, p| {
+        AABB::new(
+            V3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            V3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z)),
+        )
+    })
+}
+
+/// Placement of an animated instance at `time`: scaled first, then rotated, then translated.
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    time: f32,
+    translation: V3,
+    rotation: Quat,
+    scale: V3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32) -> Keyframe {
+        Keyframe { time, translation: V3::zeros(), rotation: Quat::identity(), scale: V3::ones() }
+    }
+
+    pub fn translate(self, offset: V3) -> Keyframe {
+        Keyframe { translation: self.translation + offset, ..self }
+    }
+
+    /// Composes with previous rotations of the keyframe.
+    pub fn rotate(self, axis: V3, angle: f64) -> Keyframe {
+        Keyframe { rotation: Quat::rotation(axis, angle) * self.rotation, ..self }
+    }
+
+    /// Zero factors make the transform singular.
+    pub fn scale(self, factors: V3) -> Keyframe {
+        Keyframe { scale: self.scale * factors, ..self }
+    }
+
+    /// Translation and scale are interpolated linearly, rotation spherically.
+    fn lerp(&self, other: &Keyframe, t: f64) -> Keyframe {
+        Keyframe {
+            time: self.time + t as f32 * (other.time - self.time),
+            translation: (1.0 - t) * self.translation + t * other.translation,
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: (1.0 - t) * self.scale + t * other.scale,
+        }
+    }
+
+    fn matrix(&self) -> M4 {
+        M4::translation(self.translation) * self.rotation.to_matrix() * M4::scaling(self.scale)
+    }
+
+    fn inverse(&self) -> M4 {
+        M4::scaling(V3::ones() / self.scale) * self.rotation.conjugate().to_matrix() * M4::translation(-self.translation)
+    }
+}
+
+/// Instance moving through keyframes by `Ray::time`, held still before the first and after the last one.
+/// Like `MovingSphere` it isn't sampled directly, so it shouldn't be important.
+#[derive(Debug, Clone)]
+pub struct Animated<T> {
+    target: T,
+    /// sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+pub trait AnimateOp<I> {
+    /// Without keyframes target stays where it is.
+    fn animate(self) -> Animated<I>;
+}
+
+impl<I: Hittable + Sized> AnimateOp<I> for I {
+    fn animate(self) -> Animated<I> {
+        Animated { target: self, keyframes: vec![] }
+    }
+}
+
+/// Steps of sampled rotation while bounding the motion.
+const MAX_BOUNDS_STEP: f64 = PI / 90.0;
+
+impl<T> Animated<T> {
+    pub fn keyframe(mut self, keyframe: Keyframe) -> Animated<T> {
+        let idx = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(idx, keyframe);
+        self
+    }
+
+    fn at(&self, time: f32) -> Keyframe {
+        let idx = self.keyframes.partition_point(|k| k.time <= time);
+        match (self.keyframes.get(idx.wrapping_sub(1)), self.keyframes.get(idx)) {
+            (Some(a), Some(b)) => a.lerp(b, ((time - a.time) / (b.time - a.time)) as f64),
+            (Some(k), None) | (None, Some(k)) => *k,
+            (None, None) => Keyframe::new(time),
+        }
+    }
+}
+
+impl<T: Hittable> Hittable for Animated<T> {
+    fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit<'_>> {
+        let keyframe = self.at(ray.time);
+        hit_transformed(&self.target, &keyframe.matrix(), &keyframe.inverse(), ray, dist_min, dist_max)
+    }
+
+    /// Target's box placed at times a rotation step apart, translation and scale alone move its corners linearly.
+    /// Padded by how far corners can leave the chords between these placements.
+    /// Covers all keyframes whatever the times asked for, so renders past them, e.g. later frames, can't miss it.
+    fn bounding_box(&self, t_min: f32, t_max: f32) -> Option<AABB> {
+        let (t_min, t_max) = match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => (t_min.min(first.time), t_max.max(last.time)),
+            _ => (t_min, t_max),
+        };
+        let corners = corners(&self.target.bounding_box(t_min, t_max)?);
+        let mut times = vec![t_min];
+        times.extend(self.keyframes.iter().map(|k| k.time).filter(|&t| t_min < t && t < t_max));
+        times.push(t_max);
+
+        let mut points = vec![];
+        let mut pad: f64 = 0.0;
+        for span in times.windows(2) {
+            let (a, b) = (self.at(span[0]), self.at(span[1]));
+            let angle = a.rotation.angle_to(&b.rotation);
+            let steps = f64::ceil(angle / MAX_BOUNDS_STEP).max(1.0);
+            let step_angle = angle / steps;
+            for &corner in corners.iter() {
+                let radius = f64::max((a.scale * corner).length(), (b.scale * corner).length());
+                let scaling = ((b.scale - a.scale) * corner).length() / steps;
+                pad = pad.max(radius * (1.0 - f64::cos(step_angle / 2.0)) + scaling * step_angle);
+            }
+            for step in 0..=steps as usize {
+                let matrix = a.lerp(&b, step as f64 / steps).matrix();
+                points.extend(corners.iter().map(|&p| matrix.transform_point(p)));
+            }
+        }
+        let aabb = bounds(points.into_iter());
+        Some(AABB::new(aabb.min - V3::all(pad), aabb.max + V3::all(pad)))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::hittable::{Sphere, Hittable, RotateYOp, Hit, AABox};
+    use crate::hittable::{Sphere, Hittable, RotateYOp, AABox, TransformOp, AnimateOp, Keyframe};
+    use crate::ray::Ray;
     use crate::texture::Color;
     use crate::vec::V3;
     use crate::material::{Dielectric, Lambertian};
@@ -233,4 +498,67 @@ mod test {
             test_pdf_integration(aabox.clone().rotate_y(angle), count);
         }
     }
+
+    fn random_scale() -> V3 {
+        V3::new(next_std_f64_in_range(&(0.5..1.5)), next_std_f64_in_range(&(0.5..1.5)), next_std_f64_in_range(&(0.5..1.5)))
+    }
+
+    #[test]
+    fn test_transform_pdf_sphere() {
+        let sphere = Sphere::new(V3::zeros(), 1.0, Dielectric::new(1.5));
+        for _ in 0..20 {
+            let ellipsoid = sphere.clone()
+                .transform()
+                .scale(random_scale())
+                .rotate(rand_in_unit_sphere(), next_std_f64_in_range(&(-180.0..180.0)))
+                .translate(6.0 * rand_in_unit_sphere());
+            test_pdf_integration(ellipsoid, 10_000);
+        }
+    }
+
+    #[test]
+    fn test_transform_pdf_aabox() {
+        let aabox = AABox::mono(-1.0..1.0, -1.0..1.0, -1.0..1.0,
+                                Arc::new(Lambertian::new(Color(V3::ones()))));
+        for _ in 0..20 {
+            let transformed = aabox.clone()
+                .transform()
+                .scale(random_scale())
+                .rotate_x(next_std_f64_in_range(&(-180.0..180.0)))
+                .rotate_z(next_std_f64_in_range(&(-180.0..180.0)))
+                .translate(6.0 * rand_in_unit_sphere());
+            test_pdf_integration(transformed, 10_000);
+        }
+    }
+
+    #[test]
+    fn test_animated_stays_in_bounds() {
+        let aabox = AABox::mono(-1.0..2.0, -1.0..1.0, -0.5..1.0,
+                                Arc::new(Lambertian::new(Color(V3::ones()))));
+        let animated = aabox.clone().animate()
+            .keyframe(Keyframe::new(0.0))
+            .keyframe(Keyframe::new(1.0).rotate(V3::new(1.0, 1.0, 0.0), 170.0).scale(V3::new(2.0, 1.0, 0.5)))
+            .keyframe(Keyframe::new(0.5).translate(V3::new(3.0, 0.0, 0.0)).rotate(V3::new(0.0, 0.0, 1.0), 90.0));
+        let aabb = animated.bounding_box(0.1, 0.9).unwrap();
+        let halfway = aabox.clone().transform().rotate_z(45.0).translate(V3::new(1.5, 0.0, 0.0));
+        for _ in 0..10_000 {
+            // held still past the last keyframe, it has to stay in bounds asked for any time
+            let time = -0.5 + 2.0 * next_std_f64() as f32;
+            let origin = 10.0 * rand_in_unit_sphere();
+            let ray = Ray::new(origin, 2.0 * rand_in_unit_sphere() - origin, V3::ones(), time, 1);
+            if let Some(hit) = animated.hit(&ray, 0.001, f64::MAX) {
+                let p = hit.point;
+                assert!(aabb.min.x <= p.x && aabb.min.y <= p.y && aabb.min.z <= p.z, "{:?} outside {:?}", p, aabb);
+                assert!(p.x <= aabb.max.x && p.y <= aabb.max.y && p.z <= aabb.max.z, "{:?} outside {:?}", p, aabb);
+            }
+
+            let ray = Ray { time: 0.25, ..ray };
+            let expected = halfway.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist);
+            let actual = animated.hit(&ray, 0.001, f64::MAX).map(|hit| hit.dist);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => assert!((expected - actual).abs() < 1e-9),
+                (expected, actual) => assert_eq!(expected, actual),
+            }
+        }
+    }
 }
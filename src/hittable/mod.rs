@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 pub use aabox::*;
 pub use aarect::*;
@@ -6,6 +7,7 @@ pub use constant_medium::*;
 pub use instance::*;
 pub use list::*;
 pub use sphere::*;
+pub use triangle::*;
 
 use crate::aabb::AABB;
 use crate::material::Material;
@@ -20,6 +22,7 @@ mod list;
 mod aabox;
 mod constant_medium;
 mod instance;
+mod triangle;
 
 #[derive(Copy, Clone)]
 pub struct Hit<'a> {
@@ -38,7 +41,7 @@ impl<'a> Hit<'a> {
 }
 
 #[allow(unused_variables)]
-pub trait Hittable: Debug + Sync {
+pub trait Hittable: Debug + Sync + Send {
     fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit>;
     fn bounding_box(&self, t_min: f32, t_max: f32) -> Option<AABB> { None }
 
@@ -89,6 +92,26 @@ impl<T:Hittable> Hittable for Box<T>
     }
 }
 
+/// Shared geometry, every `Arc` pointing to it can be placed with its own transform.
+impl<T: Hittable + ?Sized> Hittable for Arc<T>
+{
+    fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit<'_>> {
+        Hittable::hit(&**self, ray, dist_min, dist_max)
+    }
+
+    fn bounding_box(&self, t_min: f32, t_max: f32) -> Option<AABB> {
+        Hittable::bounding_box(&**self, t_min, t_max)
+    }
+
+    fn pdf_value(&self, origin: &V3, direction: &V3, hit: &Hit) -> f64 {
+        Hittable::pdf_value(&**self, origin, direction, hit)
+    }
+
+    fn random(&self, origin: &V3) -> V3 {
+        Hittable::random(&**self, origin)
+    }
+}
+
 #[derive(Debug)]
 pub struct NoHit;
 impl Hittable for NoHit{
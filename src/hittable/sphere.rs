@@ -59,9 +59,10 @@ impl MovingSphere {
             material,
         }
     }
+    /// Holds still at the end positions outside `time0..time1`.
     #[inline]
     fn center(&self, time: f32) -> V3 {
-        let scale = (time - self.time0) / self.duration;
+        let scale = ((time - self.time0) / self.duration).clamp(0.0, 1.0);
         self.center_t0 + scale * (self.center_t1 - self.center_t0)
     }
     #[inline]
@@ -156,7 +157,9 @@ impl Hittable for MovingSphere {
     }
 
     fn bounding_box(&self, t_min: f32, t_max: f32) -> Option<AABB> {
-        Some(self.aabb(t_min) + self.aabb(t_max))
+        // whole motion, so frames past `t_max` stay inside the box
+        let time1 = self.time0 + self.duration;
+        Some(self.aabb(t_min.min(self.time0)) + self.aabb(t_max.max(time1)))
     }
 
 }
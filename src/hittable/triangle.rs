@@ -0,0 +1,154 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use super::{AABB, Hit, Hittable, Material, Ray, V3};
+use crate::material::Lambertian;
+use crate::random::next_std_f64;
+use crate::texture::VertexColors;
+
+/// Triangle, front face is the one vertices go counter-clockwise on.
+/// Without texture coordinates `u` and `v` of hits are barycentric weights of the second and third vertex.
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    vertices: [V3; 3],
+    /// per-vertex normals for smooth shading
+    normals: Option<[V3; 3]>,
+    uvs: Option<[[f64; 2]; 3]>,
+    material: Arc<dyn Material>,
+    /// kept inline, so that meshes colored per vertex don't allocate a material per triangle
+    vertex_colors: Option<Lambertian<VertexColors>>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [V3; 3], material: Arc<dyn Material>) -> Triangle {
+        Triangle { vertices, normals: None, uvs: None, material, vertex_colors: None }
+    }
+
+    /// Lambertian of the colors interpolated by barycentric coordinates replaces the material,
+    /// it ignores texture coordinates.
+    pub fn with_colors(self, colors: [V3; 3]) -> Triangle {
+        Triangle { vertex_colors: Some(Lambertian::inline(VertexColors::new(colors))), ..self }
+    }
+
+    pub fn with_normals(self, normals: [V3; 3]) -> Triangle {
+        Triangle { normals: Some(normals), ..self }
+    }
+
+    pub fn with_uvs(self, uvs: [[f64; 2]; 3]) -> Triangle {
+        Triangle { uvs: Some(uvs), ..self }
+    }
+
+    fn edges(&self) -> (V3, V3) {
+        let [a, b, c] = self.vertices;
+        (b - a, c - a)
+    }
+
+    fn area(&self) -> f64 {
+        let (ab, ac) = self.edges();
+        0.5 * ab.cross(ac).length()
+    }
+}
+
+impl Hittable for Triangle {
+    /// Möller–Trumbore intersection.
+    fn hit(&self, ray: &Ray, dist_min: f64, dist_max: f64) -> Option<Hit<'_>> {
+        let (ab, ac) = self.edges();
+        let p = ray.direction.cross(ac);
+        let det = ab.dot(p);
+        if det.abs() < 1e-12 { return None; }
+        let inv_det = 1.0 / det;
+
+        let t = ray.origin - self.vertices[0];
+        let b1 = t.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&b1) { return None; }
+        let q = t.cross(ab);
+        let b2 = ray.direction.dot(q) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 { return None; }
+
+        let dist = ac.dot(q) * inv_det;
+        if !(dist_min..dist_max).contains(&dist) { return None; }
+
+        let b0 = 1.0 - b1 - b2;
+        let normal = match &self.normals {
+            Some([n0, n1, n2]) => (b0 * *n0 + b1 * *n1 + b2 * *n2).unit(),
+            None => ab.cross(ac).unit(),
+        };
+        let (u, v) = match &self.uvs {
+            Some([t0, t1, t2]) if self.vertex_colors.is_none() => (
+                b0 * t0[0] + b1 * t1[0] + b2 * t2[0],
+                b0 * t0[1] + b1 * t1[1] + b2 * t2[1],
+            ),
+            _ => (b1, b2),
+        };
+        let material: &dyn Material = match &self.vertex_colors {
+            Some(material) => material,
+            None => self.material.borrow(),
+        };
+        Some(Hit::new(dist, ray.point_at(dist), normal, material, u, v))
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let [a, b, c] = self.vertices;
+        let min = V3::new(
+            a.x.min(b.x).min(c.x),
+            a.y.min(b.y).min(c.y),
+            a.z.min(b.z).min(c.z),
+        );
+        let max = V3::new(
+            a.x.max(b.x).max(c.x),
+            a.y.max(b.y).max(c.y),
+            a.z.max(b.z).max(c.z),
+        );
+        // axis-aligned triangles would get flat boxes rays can't hit
+        Some(AABB::new(min - V3::all(0.0001), max + V3::all(0.0001)))
+    }
+
+    fn pdf_value(&self, _origin: &V3, direction: &V3, hit: &Hit) -> f64 {
+        let (ab, ac) = self.edges();
+        let cosine = direction.unit().dot(ab.cross(ac).unit());
+        let sqr_dist = hit.dist * hit.dist * direction.sqr_length();
+        sqr_dist / f64::abs(cosine * self.area())
+    }
+
+    fn random(&self, origin: &V3) -> V3 {
+        let [a, b, c] = self.vertices;
+        let r1 = next_std_f64().sqrt();
+        let r2 = next_std_f64();
+        let point = (1.0 - r1) * a + (r1 * (1.0 - r2)) * b + (r1 * r2) * c;
+        point - *origin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::hittable::{Hittable, Triangle};
+    use crate::hittable::test::test_pdf_integration;
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+    use crate::texture::Color;
+    use crate::vec::V3;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            [V3::new(-1.0, -1.0, 2.0), V3::new(3.0, -1.0, 2.0), V3::new(-1.0, 3.0, 2.0)],
+            Arc::new(Lambertian::new(Color(V3::ones()))),
+        )
+    }
+
+    #[test]
+    fn test_hit_barycentric_uv() {
+        let ray = Ray::new(V3::zeros(), V3::new(0.0, 0.0, 1.0), V3::ones(), 0.0, 1);
+        let triangle = triangle();
+        let hit = triangle.hit(&ray, 0.001, 100.0).unwrap();
+        assert!((hit.dist - 2.0).abs() < 1e-9);
+        assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+        assert!((hit.normal - V3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_pdf() {
+        test_pdf_integration(triangle(), 100_000);
+    }
+}
@@ -1,15 +1,25 @@
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use structopt::StructOpt;
 
 use vec::V3;
 
+use crate::bvh::{BvhBuilder, BvhType, SahCost};
+use crate::camera::{Shutter, ShutterCurve};
 use crate::renderer::RendererType;
 use crate::sampler::Sampler;
+use crate::sequence::{SequenceImpl, SequenceType};
 use crate::scenes::*;
 
 mod vec;
+mod matrix;
 mod ray;
 mod hittable;
 mod onb;
+mod microfacet;
+mod spectrum;
 mod pdf;
 mod scatter;
 mod camera;
@@ -23,9 +33,14 @@ mod texture;
 mod noise;
 mod renderer;
 mod sampler;
+mod output;
+mod film;
+mod sequence;
 
 #[allow(dead_code)]
 mod scenes;
+mod scene_file;
+mod mesh;
 
 #[derive(Debug, StructOpt)]
 enum SceneType {
@@ -41,6 +56,15 @@ enum SceneType {
     CornelVolumes,
     #[structopt(name = "next_week_final")]
     NextWeekFinal,
+    /// Ten thousand instances of one tree mesh
+    #[structopt(name = "forest")]
+    Forest,
+    /// Merges checkpoints of independent renders into --output (and --checkpoint)
+    #[structopt(name = "merge")]
+    Merge {
+        #[structopt(parse(from_os_str), required = true)]
+        checkpoints: Vec<PathBuf>,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -54,40 +78,200 @@ struct Params {
     width: u16,
     #[structopt(short = "h", long = "height", default_value = "512")]
     height: u16,
+    /// Samples per pixel, maximum when sampling adaptively
     #[structopt(short = "s", long = "samples", default_value = "400")]
     samples: u16,
     #[structopt(short = "b", long = "bounces", default_value = "12")]
     bounces: u16,
+    /// Output image, format is picked by extension: png, ppm or hdr (linear, unclamped)
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+    #[structopt(long = "tile-size", default_value = "32")]
+    tile_size: u32,
+    /// Samples per pixel added to the whole image before moving to the next pass
+    #[structopt(long = "pass-samples", default_value = "64")]
+    pass_samples: u16,
+    /// Seconds between writes of the partially rendered image to the output file
+    #[structopt(long = "progress-interval")]
+    progress_interval: Option<u64>,
+    /// Enables adaptive sampling: pixel stops when relative error of its mean drops below this
+    #[structopt(long = "adaptive-threshold")]
+    adaptive_threshold: Option<f64>,
+    /// Samples every pixel gets before adaptive sampling may stop it
+    #[structopt(long = "min-samples", default_value = "16")]
+    min_samples: u16,
+    /// Writes grayscale image of samples spent per pixel
+    #[structopt(long = "heatmap", parse(from_os_str))]
+    heatmap: Option<PathBuf>,
+    /// Seed of per-pixel random streams, scene layout doesn't depend on it
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+    /// Sample sequence for camera and BSDF dimensions: independent, stratified, halton or sobol
+    #[structopt(long = "sequence", default_value = "independent")]
+    sequence: SequenceType,
+    /// File to periodically save accumulated samples to
+    #[structopt(long = "checkpoint", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+    /// Seconds between checkpoints
+    #[structopt(long = "checkpoint-interval", default_value = "300")]
+    checkpoint_interval: u64,
+    /// Continue adding samples to a checkpoint, up to --samples per pixel
+    #[structopt(long = "resume", parse(from_os_str))]
+    resume: Option<PathBuf>,
+    /// Acceleration structure builder: median (random axis) or sah (surface area heuristic)
+    #[structopt(long = "bvh", default_value = "sah")]
+    bvh: BvhType,
+    /// SAH cost of visiting a node, relative to intersecting a primitive
+    #[structopt(long = "sah-traversal-cost", default_value = "0.125")]
+    sah_traversal_cost: f64,
+    /// SAH cost of intersecting a primitive
+    #[structopt(long = "sah-intersection-cost", default_value = "1")]
+    sah_intersection_cost: f64,
+    /// Candidate split planes per axis considered by SAH
+    #[structopt(long = "sah-bins", default_value = "16")]
+    sah_bins: usize,
+    /// Scene description (TOML) to render instead of a built-in scene
+    #[structopt(long = "scene-file", parse(from_os_str))]
+    scene_file: Option<PathBuf>,
+    /// Frames to render as `start..end`, output files are numbered by frame
+    #[structopt(long = "frames", parse(try_from_str = parse_frames))]
+    frames: Option<Range<u32>>,
+    /// Frames per second of the sequence
+    #[structopt(long = "fps", default_value = "24")]
+    fps: f32,
+    /// Fraction of the frame time shutter is open for
+    #[structopt(long = "shutter", default_value = "0.5")]
+    shutter: f32,
+    /// Distribution of ray time over the shutter interval: box, triangle or comma separated curve values
+    #[structopt(long = "shutter-curve", default_value = "box")]
+    shutter_curve: ShutterCurve,
+    /// Fraction of the shutter interval taken by reading rows out from top to bottom, 0 for global shutter
+    #[structopt(long = "rolling-shutter", default_value = "0", parse(try_from_str = parse_fraction))]
+    rolling_shutter: f64,
+}
+
+fn parse_frames(src: &str) -> Result<Range<u32>, String> {
+    let err = || format!("Expected frame range as 'start..end', got: '{}'", src);
+    let mut bounds = src.splitn(2, "..");
+    let start = bounds.next().and_then(|start| start.trim().parse().ok()).ok_or_else(err)?;
+    let end = bounds.next().and_then(|end| end.trim().parse().ok()).ok_or_else(err)?;
+    if start < end { Ok(start..end) } else { Err(err()) }
+}
+
+fn parse_fraction(src: &str) -> Result<f64, String> {
+    match src.parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(format!("Expected value in 0..1, got: '{}'", src)),
+    }
 }
 
 fn main() {
     let params: Params = Params::from_args();
+    if params.frames.is_some() && (params.output.is_none() || params.resume.is_some()) {
+        eprintln!("--frames needs --output and can't be resumed");
+        std::process::exit(1);
+    }
+    for path in params.output.iter().chain(params.heatmap.iter()) {
+        if let Err(msg) = output::ImageFormat::from_path(path) {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    }
     let cfg = Sampler {
         width: params.width as u32,
         height: params.height as u32,
         samples: params.samples as usize,
         max_ray_bounces: params.bounces as i32,
         pixel_postprocessor: crate::postprocess,
+        output: params.output,
+        tile_size: params.tile_size,
+        pass_samples: params.pass_samples as usize,
+        progress_interval: params.progress_interval.map(Duration::from_secs),
+        adaptive_threshold: params.adaptive_threshold,
+        min_samples: params.min_samples as usize,
+        heatmap: params.heatmap,
+        seed: params.seed,
+        sequence: SequenceImpl::pick(params.sequence, params.samples as usize),
+        checkpoint: params.checkpoint,
+        checkpoint_interval: Duration::from_secs(params.checkpoint_interval),
+        resume: params.resume,
     };
 
     let w = cfg.width;
     let h = cfg.height;
     let ttl = cfg.max_ray_bounces;
     let renderer_type = params.renderer_type.unwrap_or(RendererType::RGBBiased);
+    let bvh = BvhBuilder::pick(params.bvh, SahCost {
+        traversal: params.sah_traversal_cost,
+        intersection: params.sah_intersection_cost,
+        bins: params.sah_bins,
+    });
 
-    let scene: Scene = match params.scene.unwrap_or(SceneType::WeekendFinal) {
-        SceneType::WeekendFinal => weekend_final(renderer_type, 11, w, h, 0.0, 0.2, ttl),
-        SceneType::CornelInstances => cornel_box_with_instances(renderer_type, w, h, 0.0, 0.2, ttl),
-        SceneType::CornelIs => cornel_box_with_is(renderer_type, w, h, 0.0, 0.2, ttl),
-        SceneType::CornelVolumes => cornel_box_volumes(renderer_type, w, h, 0.0, 0.2, ttl),
-        SceneType::NextWeekFinal => next_week(renderer_type, w, h, 0.0, 0.2, ttl),
-        SceneType::Perlin => perlin_scene(renderer_type, w, h, 0.0, 0.2, ttl),
+    if let Some(SceneType::Merge { checkpoints }) = &params.scene {
+        if let Err(err) = cfg.merge(checkpoints) {
+            eprintln!("Merge failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let scene_params = SceneParams { nx: w, ny: h, t_off: 0.0, t_span: 0.2, ttl };
+    let mut camera_path = None;
+    let mut scene: Scene = match (&params.scene_file, params.scene.unwrap_or(SceneType::WeekendFinal)) {
+        (Some(path), _) => match scene_file::load(path, bvh) {
+            Ok(world) => {
+                camera_path = Some(world.camera_path().clone());
+                world.scene(renderer_type, scene_params)
+            }
+            Err(msg) => {
+                eprintln!("Can't load scene: {}", msg);
+                std::process::exit(1);
+            }
+        },
+        (None, SceneType::Merge { .. }) => unreachable!(),
+        (None, SceneType::WeekendFinal) => weekend_final(renderer_type, bvh, 11, scene_params),
+        (None, SceneType::CornelInstances) => cornel_box_with_instances(renderer_type, scene_params),
+        (None, SceneType::CornelIs) => cornel_box_with_is(renderer_type, scene_params),
+        (None, SceneType::CornelVolumes) => cornel_box_volumes(renderer_type, scene_params),
+        (None, SceneType::NextWeekFinal) => next_week(renderer_type, bvh, scene_params),
+        (None, SceneType::Forest) => forest(renderer_type, bvh, scene_params),
+        (None, SceneType::Perlin) => perlin_scene(renderer_type, scene_params),
     };
 //    let scene = img_scene(cfg.width, cfg.height, 0.0, 0.2, cfg.max_ray_bounces);
 //    let scene = img_lit_scene(cfg.width, cfg.height, 0.0, 0.2, cfg.max_ray_bounces);
 //    let scene = img_lit_rect_scene(cfg.width, cfg.height, 0.0, 0.2, cfg.max_ray_bounces);
 
-    cfg.do_render(scene);
+    let aspect = (w as f64) / (h as f64);
+    let (curve, rolling) = (params.shutter_curve, params.rolling_shutter);
+    let shutter = |t0: f32, t1: f32| Shutter::new(t0, t1)
+        .with_curve(curve.clone())
+        .with_rolling(rolling);
+    let frames = match params.frames {
+        Some(frames) => frames,
+        None => {
+            scene.camera = match &camera_path {
+                Some(path) => path.camera(aspect, shutter(0.0, 0.2), ttl),
+                None => scene.camera.with_shutter(shutter(0.0, 0.2)),
+            };
+            if let Err(err) = cfg.do_render(&scene) {
+                eprintln!("Rendering failed: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+    for frame in frames {
+        let t0 = frame as f32 / params.fps;
+        let t1 = t0 + params.shutter / params.fps;
+        scene.camera = match &camera_path {
+            Some(path) => path.camera(aspect, shutter(t0, t1), ttl),
+            None => scene.camera.with_shutter(shutter(t0, t1)),
+        };
+        eprintln!("Frame {}", frame);
+        if let Err(err) = cfg.frame(frame).do_render(&scene) {
+            eprintln!("Rendering frame {} failed: {}", frame, err);
+            std::process::exit(1);
+        }
+    }
 }
 
 pub fn postprocess(color: V3) -> V3 {
@@ -0,0 +1,109 @@
+use crate::microfacet::Ggx;
+use crate::onb::ONB;
+use crate::pdf::{GgxPDF, PDF};
+use crate::random::next_sample_2d;
+use crate::scatter::Scatter;
+
+use super::{Color, Hit, Material, Ray, V3};
+
+/// Metal with GGX microfacets and Fresnel reflectance of its complex index of refraction `eta + ik`.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Conductor {
+    eta: V3,
+    k: V3,
+    ggx: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: V3, k: V3, roughness: f64) -> Conductor {
+        Conductor { eta, k, ggx: Ggx::new(roughness) }
+    }
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(V3::new(0.143, 0.374, 1.442), V3::new(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(V3::new(0.200, 0.924, 1.102), V3::new(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(V3::new(1.657, 0.880, 0.521), V3::new(9.224, 6.270, 4.837), roughness)
+    }
+
+    pub fn fresnel(&self, cosine: f64) -> V3 {
+        V3::new(
+            fresnel_conductor(cosine, self.eta.x, self.k.x),
+            fresnel_conductor(cosine, self.eta.y, self.k.y),
+            fresnel_conductor(cosine, self.eta.z, self.k.z),
+        )
+    }
+}
+
+/// Unpolarized reflectance of conductor in vacuum, `cosine` of the incident angle.
+pub fn fresnel_conductor(cosine: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cosine * cosine;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = f64::sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+    let a = f64::sqrt(f64::max(0.5 * (a2_plus_b2 + t0), 0.0));
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cosine * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        let onb = ONB::from_w(&hit.normal);
+        let wo = onb.project(-ray.direction.unit());
+        if wo.z <= 0.0 { return None; }
+        let h = self.ggx.sample_visible(wo, next_sample_2d());
+        let wi = (-wo).reflect(h);
+        if wi.z <= 0.0 { return None; }
+        // visible normals sampling leaves only masking of the reflected direction in the weight
+        let weight = (self.ggx.g2(wo, wi) / self.ggx.g1(wo)) * self.fresnel(wo.dot(h));
+        Some(ray.produce(hit.point, onb.local(wi), weight))
+    }
+
+    fn scatter_with_pdf(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        if ray.direction.dot(hit.normal) >= 0.0 { return None; }
+        Some(Scatter::Diffuse(Box::new(GgxPDF::new(&hit.normal, &-ray.direction, self.ggx)), Color(V3::ones())))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> f64 {
+        GgxPDF::new(&hit.normal, &-ray.direction, self.ggx).value(direction, hit)
+    }
+
+    fn scattering(&self, ray: &Ray, hit: &Hit, direction: &V3) -> V3 {
+        let onb = ONB::from_w(&hit.normal);
+        let wo = onb.project(-ray.direction.unit());
+        let wi = onb.project(direction.unit());
+        if wo.z <= 0.0 || wi.z <= 0.0 { return V3::zeros(); }
+        let h = (wo + wi).unit();
+        (self.ggx.d(h) * self.ggx.g2(wo, wi) / (4.0 * wo.z)) * self.fresnel(wo.dot(h))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::material::conductor::fresnel_conductor;
+    use crate::material::Conductor;
+
+    #[test]
+    fn test_fresnel() {
+        // dielectric limit matches reflectance at normal incidence
+        assert!((fresnel_conductor(1.0, 1.5, 0.0) - 0.04).abs() < 1e-9);
+        let gold = Conductor::gold(0.0);
+        let normal = gold.fresnel(1.0);
+        assert!(normal.x > normal.y && normal.y > normal.z);
+        // all metals turn white at grazing angles
+        let grazing = gold.fresnel(1e-6);
+        assert!(grazing.z > 0.99);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::random;
+use crate::spectrum;
 
 use super::{Hit, Material, Ray, V3};
 
@@ -6,19 +7,84 @@ use super::{Hit, Material, Ray, V3};
 pub struct Dielectric {
     albedo: V3,
     ref_idx: f64,
+    absorption: V3,
+    dispersion: Option<Ior>,
+}
+
+/// Index of refraction changing with wavelength.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Ior {
+    /// `a + b / λ²`, wavelength in micrometers
+    Cauchy { a: f64, b: f64 },
+    /// `sqrt(1 + Σ b λ² / (λ² - c))`, wavelength in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /// Borosilicate crown glass (Schott N-BK7).
+    pub fn crown() -> Ior {
+        Ior::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] }
+    }
+
+    /// Dense flint glass (Schott SF11), prisms.
+    pub fn flint() -> Ior {
+        Ior::Sellmeier { b: [1.73759695, 0.313747346, 1.89878101], c: [0.013188707, 0.0623068142, 155.23629] }
+    }
+
+    pub fn diamond() -> Ior {
+        Ior::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030625, 0.011236, 0.0] }
+    }
+
+    /// Index of refraction usually quoted, at the yellow helium line.
+    pub fn ref_idx(&self) -> f64 {
+        self.at(587.6)
+    }
+
+    /// `wavelength` in nanometers.
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let l2 = wavelength * wavelength * 1e-6;
+        match self {
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } =>
+                f64::sqrt(1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()),
+        }
+    }
 }
 
 impl Dielectric {
-    pub fn new(ref_idx: f64) -> Dielectric { Dielectric { albedo: V3::ones(), ref_idx } }
+    pub fn new(ref_idx: f64) -> Dielectric { Dielectric::new_colored(V3::ones(), ref_idx) }
     pub fn new_colored(albedo: V3, ref_idx: f64) -> Dielectric {
-        Dielectric { albedo, ref_idx }
+        Dielectric { albedo, ref_idx, absorption: V3::zeros(), dispersion: None }
+    }
+
+    /// Splits light into wavelengths refracted by `ior`.
+    pub fn with_dispersion(self, ior: Ior) -> Dielectric {
+        Dielectric { ref_idx: ior.ref_idx(), dispersion: Some(ior), ..self }
+    }
+
+    /// Light inside is attenuated by `exp(-absorption * distance)`.
+    pub fn with_absorption(self, absorption: V3) -> Dielectric {
+        Dielectric { absorption, ..self }
     }
-    fn schlick(self, cosine: f64) -> f64 {
-        let mut r0 = (1.0 - self.ref_idx) / (1.0 + self.ref_idx);
+
+    fn schlick(ref_idx: f64, cosine: f64) -> f64 {
+        let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 *= r0;
         return r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0);
     }
 
+    /// Ray of a single wavelength once dispersed, its index of refraction and weight of its color.
+    fn dispersed(&self, ray: &Ray) -> (Ray, f64, V3) {
+        match (self.dispersion, ray.wavelength) {
+            (None, _) => (*ray, self.ref_idx, V3::ones()),
+            (Some(ior), Some(wavelength)) => (*ray, ior.at(wavelength), V3::ones()),
+            (Some(ior), None) => {
+                let (wavelength, weight) = spectrum::sample_wavelength(random::next_sample_1d());
+                (ray.with_wavelength(wavelength), ior.at(wavelength), weight)
+            }
+        }
+    }
+
 
     fn refract(v: V3, normal: V3, ni_over_nt: f64) -> Option<V3> {
         let unit = v.unit();
@@ -32,8 +98,11 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, ray: &Ray, &hit: &Hit) -> Option<Ray> {
+        let (ray, ref_idx, weight) = self.dispersed(ray);
         let unit_direction = ray.direction.unit();
 
+        let transmittance = weight * transmittance(self.absorption, &ray, &hit);
+
         let cosine: f64;
         let outward_normal: V3;
         let ni_over_nt: f64;
@@ -41,11 +110,11 @@ impl Material for Dielectric {
         let vector_cosine = unit_direction.dot(hit.normal);
         if vector_cosine > 0.0 {
             outward_normal = -hit.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = (1.0 - self.ref_idx * self.ref_idx * (1.0 - vector_cosine * vector_cosine)).sqrt();
+            ni_over_nt = ref_idx;
+            cosine = (1.0 - ref_idx * ref_idx * (1.0 - vector_cosine * vector_cosine)).sqrt();
         } else {
             outward_normal = hit.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
+            ni_over_nt = 1.0 / ref_idx;
             cosine = -vector_cosine;
         }
 
@@ -53,8 +122,75 @@ impl Material for Dielectric {
         let reflected = ray.direction.reflect(hit.normal);
 
         refracted
-            .filter(|_| self.schlick(cosine) < random::next_std_f64())
-            .map(|refracted| ray.produce(hit.point, refracted, self.albedo))
-            .or_else(|| Some(ray.produce(hit.point, reflected, V3::ones())))
+            .filter(|_| Dielectric::schlick(ref_idx, cosine) < random::next_sample_1d())
+            .map(|refracted| ray.produce(hit.point, refracted, self.albedo * transmittance))
+            .or_else(|| Some(ray.produce(hit.point, reflected, transmittance)))
+    }
+}
+
+/// Absorption coefficient turning white light into `color` over `distance`.
+pub fn absorption_for(color: V3, distance: f64) -> V3 {
+    V3::new(-color.x.ln(), -color.y.ln(), -color.z.ln()) / distance
+}
+
+/// Beer-Lambert attenuation of `ray` by `absorption` on its way to `hit` from inside the medium.
+pub fn transmittance(absorption: V3, ray: &Ray, hit: &Hit) -> V3 {
+    if ray.direction.dot(hit.normal) <= 0.0 { return V3::ones(); }
+    let distance = hit.dist * ray.direction.length();
+    V3::new(
+        f64::exp(-absorption.x * distance),
+        f64::exp(-absorption.y * distance),
+        f64::exp(-absorption.z * distance),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hittable::Hit;
+    use crate::material::dielectric::{absorption_for, transmittance};
+    use crate::material::{Dielectric, Ior, Material};
+    use crate::ray::Ray;
+    use crate::vec::V3;
+
+    #[test]
+    fn test_absorption() {
+        let color = V3::new(0.9, 0.5, 0.1);
+        let absorption = absorption_for(color, 2.0);
+        let glass = Dielectric::new(1.5);
+        // leaving the glass after 1 and 2 units inside
+        let ray = Ray::new(V3::zeros(), V3::new(0.0, 0.0, 2.0), V3::ones(), 0.0, 1);
+        let thin = transmittance(absorption, &ray, &Hit::new(0.5, V3::new(0.0, 0.0, 1.0), V3::new(0.0, 0.0, 1.0), &glass, 0.0, 0.0));
+        let thick = transmittance(absorption, &ray, &Hit::new(1.0, V3::new(0.0, 0.0, 2.0), V3::new(0.0, 0.0, 1.0), &glass, 0.0, 0.0));
+        assert!((thick - color).length() < 1e-9);
+        assert!((thin * thin - color).length() < 1e-9);
+        // entering the glass
+        let entering = Ray::new(V3::zeros(), V3::new(0.0, 0.0, -2.0), V3::ones(), 0.0, 1);
+        let outside = transmittance(absorption, &entering, &Hit::new(1.0, V3::zeros(), V3::new(0.0, 0.0, 1.0), &glass, 0.0, 0.0));
+        assert_eq!(outside, V3::ones());
+    }
+
+    #[test]
+    fn test_thick_slab_absorbs_more() {
+        let glass = Dielectric::new(1.5).with_absorption(absorption_for(V3::new(0.9, 0.5, 0.1), 1.0));
+        // leaving slabs of 0.5 and 2 units, whether reflected back or refracted out
+        let ray = Ray::new(V3::zeros(), V3::new(0.0, 0.0, 1.0), V3::ones(), 0.0, 1);
+        let leave = |thickness: f64| {
+            let hit = Hit::new(thickness, V3::new(0.0, 0.0, thickness), V3::new(0.0, 0.0, 1.0), &glass, 0.0, 0.0);
+            glass.scatter(&ray, &hit).unwrap().attenuation
+        };
+        let (thin, thick) = (leave(0.5), leave(2.0));
+        assert!(thick.x < thin.x && thick.y < thin.y && thick.z < thin.z, "{:?} {:?}", thin, thick);
+        assert!((thick - V3::new(0.81, 0.25, 0.01)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // catalog values of Schott glasses at the yellow helium line
+        assert!((Ior::crown().ref_idx() - 1.5168).abs() < 1e-4);
+        assert!((Ior::flint().ref_idx() - 1.7847).abs() < 1e-4);
+        assert!((Ior::Cauchy { a: 1.5, b: 0.01 }.at(500.0) - 1.54).abs() < 1e-9);
+        for ior in &[Ior::crown(), Ior::flint(), Ior::diamond()] {
+            assert!(ior.at(450.0) > ior.at(650.0));
+        }
     }
 }
@@ -49,7 +49,7 @@ impl<T: Texture> Material for Isotropic<T> {
 
     //todo: check that integrates to the same value as others
     #[inline]
-    fn scattering_pdf(&self, _hit: &Hit, _direction: &V3) -> f64 {
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &Hit, _direction: &V3) -> f64 {
         // 1/ (4*pi), where 4*pi is the solid angle of full sphere
         0.25 * consts::FRAC_1_PI
     }
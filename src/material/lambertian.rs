@@ -4,9 +4,10 @@ use super::{Hit, Material, Ray, V3};
 use crate::scatter::Scatter;
 use crate::pdf::{CosinePDF, PDF};
 
-#[derive(Debug)]
-pub struct Lambertian {
-    texture: Box<dyn Texture>
+/// Texture is boxed, unless a concrete one is kept inline, e.g. for vertex colors of every triangle.
+#[derive(Debug, Clone)]
+pub struct Lambertian<T: Texture = Box<dyn Texture>> {
+    texture: T
 }
 
 impl Lambertian {
@@ -16,7 +17,11 @@ impl Lambertian {
     pub fn texture(texture: Box<dyn Texture>) -> Lambertian { Lambertian { texture } }
 }
 
-impl Material for Lambertian {
+impl<T: Texture> Lambertian<T> {
+    pub fn inline(texture: T) -> Lambertian<T> { Lambertian { texture } }
+}
+
+impl<T: Texture> Material for Lambertian<T> {
     fn scatter(&self, ray: &Ray, &hit: &Hit) -> Option<Ray> {
         let albedo = self.texture.value(hit.u, hit.v, hit.point);
         let target = CosinePDF::from_w(&hit.normal).generate();
@@ -28,7 +33,7 @@ impl Material for Lambertian {
         Some(Scatter::Diffuse(Box::new(CosinePDF::from_w(&hit.normal)), albedo))
     }
 
-    fn scattering_pdf(&self, hit: &Hit, direction: &V3) -> f64 {
+    fn scattering_pdf(&self, _: &Ray, hit: &Hit, direction: &V3) -> f64 {
         CosinePDF::from_w(&hit.normal).value(direction, hit)
     }
 }
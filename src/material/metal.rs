@@ -31,7 +31,7 @@ impl Material for Metal {
     }
 
     #[allow(unused_variables)]
-    fn scattering_pdf(&self, hit: &Hit, direction: &V3) -> f64 {
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> f64 {
         0.0
     }
 
@@ -1,10 +1,14 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 pub use dielectric::*;
+pub use rough_dielectric::*;
 pub use diffuse_light::*;
 pub use lambertian::*;
 pub use metal::*;
+pub use conductor::*;
 pub use isotropic::*;
+pub use principled::*;
 
 use crate::hittable::Hit;
 use crate::ray::Ray;
@@ -14,9 +18,12 @@ use crate::scatter::Scatter;
 
 pub mod lambertian;
 pub mod metal;
+pub mod conductor;
 pub mod dielectric;
+pub mod rough_dielectric;
 pub mod diffuse_light;
 pub mod isotropic;
+pub mod principled;
 
 type PDF = f64;
 
@@ -28,5 +35,31 @@ pub trait Material: Debug + Sync + Send {
     fn scatter_with_pdf(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
         self.scatter(ray, hit).map(|ray| Scatter::Specular(ray))
     }
-    fn scattering_pdf(&self, hit: &Hit, direction: &V3) -> PDF { 0.0 }
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> PDF { 0.0 }
+    /// BSDF times cosine towards `direction`, relative to attenuation of `Scatter::Diffuse`.
+    fn scattering(&self, ray: &Ray, hit: &Hit, direction: &V3) -> V3 {
+        V3::all(self.scattering_pdf(ray, hit, direction))
+    }
+}
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        (**self).scatter(ray, hit)
+    }
+
+    fn emmit(&self, hit: &Hit) -> Color {
+        (**self).emmit(hit)
+    }
+
+    fn scatter_with_pdf(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        (**self).scatter_with_pdf(ray, hit)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> PDF {
+        (**self).scattering_pdf(ray, hit, direction)
+    }
+
+    fn scattering(&self, ray: &Ray, hit: &Hit, direction: &V3) -> V3 {
+        (**self).scattering(ray, hit, direction)
+    }
 }
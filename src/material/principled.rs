@@ -0,0 +1,232 @@
+use std::f64::consts::FRAC_1_PI;
+
+use crate::microfacet::Ggx;
+use crate::onb::ONB;
+use crate::pdf::{CosinePDF, GgxDielectricPDF, GgxPDF, PDF, WeightedPDF};
+use crate::scatter::Scatter;
+
+use super::{Color, Hit, Material, Ray, Texture, V3};
+
+/// Principled BSDF after Burley (2012, 2015): diffuse with retro-reflection and sheen,
+/// GGX specular turning into metal with `metallic`, clearcoat and rough glass transmission.
+/// Scalar parameters take the first channel of their texture.
+#[derive(Debug)]
+pub struct Principled {
+    base_color: Box<dyn Texture>,
+    metallic: Box<dyn Texture>,
+    roughness: Box<dyn Texture>,
+    specular: Box<dyn Texture>,
+    clearcoat: Box<dyn Texture>,
+    clearcoat_gloss: Box<dyn Texture>,
+    sheen: Box<dyn Texture>,
+    transmission: Box<dyn Texture>,
+    ior: f64,
+}
+
+/// Parameters at a hit.
+struct Lobes {
+    base_color: V3,
+    metallic: f64,
+    roughness: f64,
+    /// reflectance at normal incidence
+    f0: V3,
+    sheen: V3,
+    clearcoat: f64,
+    transmission: f64,
+    ggx: Ggx,
+    clearcoat_ggx: Ggx,
+}
+
+impl Principled {
+    pub fn new(base_color: Box<dyn Texture>) -> Principled {
+        Principled {
+            base_color,
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            specular: scalar(0.5),
+            clearcoat: scalar(0.0),
+            clearcoat_gloss: scalar(1.0),
+            sheen: scalar(0.0),
+            transmission: scalar(0.0),
+            ior: 1.5,
+        }
+    }
+
+    pub fn with_metallic(self, metallic: Box<dyn Texture>) -> Principled {
+        Principled { metallic, ..self }
+    }
+
+    pub fn with_roughness(self, roughness: Box<dyn Texture>) -> Principled {
+        Principled { roughness, ..self }
+    }
+
+    /// Reflectance of the dielectric part, 0.5 is the usual 4%.
+    pub fn with_specular(self, specular: Box<dyn Texture>) -> Principled {
+        Principled { specular, ..self }
+    }
+
+    pub fn with_clearcoat(self, clearcoat: Box<dyn Texture>, gloss: Box<dyn Texture>) -> Principled {
+        Principled { clearcoat, clearcoat_gloss: gloss, ..self }
+    }
+
+    /// Color of the cloth-like rim at grazing angles.
+    pub fn with_sheen(self, sheen: Box<dyn Texture>) -> Principled {
+        Principled { sheen, ..self }
+    }
+
+    /// Fraction of the dielectric part that is rough glass of `ior` tinted by the base color.
+    pub fn with_transmission(self, transmission: Box<dyn Texture>, ior: f64) -> Principled {
+        Principled { transmission, ior, ..self }
+    }
+
+    fn lobes(&self, hit: &Hit) -> Lobes {
+        let value = |texture: &dyn Texture| texture.value(hit.u, hit.v, hit.point).0;
+        let base_color = value(self.base_color.as_ref());
+        let metallic = value(self.metallic.as_ref()).x;
+        let roughness = value(self.roughness.as_ref()).x;
+        let dielectric = V3::all(0.08 * value(self.specular.as_ref()).x);
+        // clearcoat alpha from 0.1 to 0.001, as in Disney's
+        let clearcoat_alpha = 0.1 - 0.099 * value(self.clearcoat_gloss.as_ref()).x;
+        Lobes {
+            base_color,
+            metallic,
+            roughness,
+            f0: (1.0 - metallic) * dielectric + metallic * base_color,
+            sheen: value(self.sheen.as_ref()),
+            clearcoat: value(self.clearcoat.as_ref()).x,
+            transmission: value(self.transmission.as_ref()).x,
+            ggx: Ggx::new(roughness),
+            clearcoat_ggx: Ggx::new(clearcoat_alpha.sqrt()),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit) -> Option<Box<dyn PDF>> {
+        let lobes = self.lobes(hit);
+        let wo = -ray.direction;
+        let glass = (1.0 - lobes.metallic) * lobes.transmission;
+        if wo.dot(hit.normal) <= 0.0 {
+            // only the way out of the glass is inside
+            if glass <= 0.0 { return None; }
+            return Some(Box::new(GgxDielectricPDF::new(&-hit.normal, &wo, 1.0 / self.ior, lobes.ggx)));
+        }
+        let cosine = wo.unit().dot(hit.normal);
+        Some(Box::new(WeightedPDF::new(vec![
+            ((1.0 - lobes.metallic) * (1.0 - lobes.transmission), Box::new(CosinePDF::from_w(&hit.normal))),
+            ((1.0 - glass) * schlick(lobes.f0, cosine).luminance(), Box::new(GgxPDF::new(&hit.normal, &wo, lobes.ggx))),
+            (0.25 * lobes.clearcoat * schlick(V3::all(0.04), cosine).x, Box::new(GgxPDF::new(&hit.normal, &wo, lobes.clearcoat_ggx))),
+            (glass, Box::new(GgxDielectricPDF::new(&hit.normal, &wo, self.ior, lobes.ggx))),
+        ])))
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        let pdf = self.pdf(ray, hit)?;
+        let direction = pdf.generate();
+        let weight = self.scattering(ray, hit, &direction) / pdf.value(&direction, hit);
+        // lost samples of microfacet lobes have no direction
+        if !weight.is_finite() { return None; }
+        Some(ray.produce(hit.point, direction, weight))
+    }
+
+    fn scatter_with_pdf(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        self.pdf(ray, hit).map(|pdf| Scatter::Diffuse(pdf, Color(V3::ones())))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> f64 {
+        self.pdf(ray, hit).map_or(0.0, |pdf| pdf.value(direction, hit))
+    }
+
+    fn scattering(&self, ray: &Ray, hit: &Hit, direction: &V3) -> V3 {
+        let lobes = self.lobes(hit);
+        let glass = (1.0 - lobes.metallic) * lobes.transmission;
+        if ray.direction.dot(hit.normal) > 0.0 {
+            if glass <= 0.0 { return V3::zeros(); }
+            let onb = ONB::from_w(&-hit.normal);
+            let (reflected, refracted) = lobes.ggx.dielectric(
+                onb.project(-ray.direction.unit()),
+                onb.project(direction.unit()),
+                1.0 / self.ior,
+            );
+            return V3::all(reflected) + refracted * lobes.base_color;
+        }
+
+        let onb = ONB::from_w(&hit.normal);
+        let wo = onb.project(-ray.direction.unit());
+        let wi = onb.project(direction.unit());
+        if wo.z <= 0.0 { return V3::zeros(); }
+        let mut scattering = V3::zeros();
+        if glass > 0.0 {
+            let (reflected, refracted) = lobes.ggx.dielectric(wo, wi, self.ior);
+            scattering += glass * (V3::all(reflected) + refracted * lobes.base_color);
+        }
+        if wi.z <= 0.0 { return scattering; }
+
+        let h = (wo + wi).unit();
+        let cos_d = wi.dot(h);
+        let retro = 0.5 + 2.0 * lobes.roughness * cos_d * cos_d;
+        let diffuse = FRAC_1_PI * (1.0 + (retro - 1.0) * fresnel_weight(wi.z)) * (1.0 + (retro - 1.0) * fresnel_weight(wo.z))
+            * lobes.base_color + fresnel_weight(cos_d) * lobes.sheen;
+        scattering += ((1.0 - lobes.metallic) * (1.0 - lobes.transmission) * wi.z) * diffuse;
+
+        let specular = lobes.ggx.d(h) * lobes.ggx.g2(wo, wi) / (4.0 * wo.z);
+        scattering += ((1.0 - glass) * specular) * schlick(lobes.f0, cos_d);
+
+        let clearcoat = lobes.clearcoat_ggx.d(h) * lobes.clearcoat_ggx.g2(wo, wi) / (4.0 * wo.z);
+        scattering += (0.25 * lobes.clearcoat * clearcoat) * schlick(V3::all(0.04), cos_d);
+        scattering
+    }
+}
+
+fn scalar(value: f64) -> Box<dyn Texture> {
+    Box::new(Color(V3::all(value)))
+}
+
+fn fresnel_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).max(0.0).powi(5)
+}
+
+fn schlick(f0: V3, cosine: f64) -> V3 {
+    f0 + fresnel_weight(cosine) * (V3::ones() - f0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use crate::hittable::Hit;
+    use crate::material::{Material, Principled};
+    use crate::random::rand_in_unit_sphere;
+    use crate::ray::Ray;
+    use crate::texture::Color;
+    use crate::vec::V3;
+
+    #[test]
+    fn test_principled_samples_match_scattering() {
+        let white = || Box::new(Color(V3::all(0.9)));
+        let value = |v: f64| Box::new(Color(V3::all(v)));
+        let materials = [
+            Principled::new(white()),
+            Principled::new(white()).with_metallic(value(1.0)).with_roughness(value(0.6)),
+            Principled::new(white()).with_clearcoat(value(1.0), value(0.5)).with_sheen(value(0.5)),
+            Principled::new(white()).with_transmission(value(0.7), 1.5).with_roughness(value(0.8)),
+        ];
+        let ray = Ray::new(V3::new(-1.0, 1.0, 0.3), V3::new(1.0, -1.0, -0.3), V3::ones(), 0.0, 2);
+        for material in materials.iter() {
+            let hit = Hit::new(1.0, V3::zeros(), V3::new(0.0, 1.0, 0.0), material, 0.0, 0.0);
+            let count = 200_000;
+            let (mut uniform, mut sampled) = (V3::zeros(), V3::zeros());
+            for _ in 0..count {
+                let direction = rand_in_unit_sphere().unit();
+                uniform += 4.0 * PI * material.scattering(&ray, &hit, &direction);
+                if let Some(scattered) = material.scatter(&ray, &hit) {
+                    sampled += scattered.attenuation;
+                }
+            }
+            // the same reflectance, estimated by uniform directions and by the material's own
+            let (uniform, sampled) = (uniform / count as f64, sampled / count as f64);
+            assert!((uniform - sampled).length() < 0.03, "{:?} {:?} {:?}", material, uniform, sampled);
+            assert!(sampled.x < 1.0);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::microfacet::Ggx;
+use crate::onb::ONB;
+use crate::pdf::{GgxDielectricPDF, PDF};
+use crate::random::{next_sample_1d, next_sample_2d};
+use crate::scatter::Scatter;
+
+use super::dielectric::transmittance;
+use super::{Color, Hit, Material, Ray, V3};
+
+/// Frosted glass, refracting and reflecting off GGX microfacets.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct RoughDielectric {
+    albedo: V3,
+    ref_idx: f64,
+    ggx: Ggx,
+    absorption: V3,
+}
+
+impl RoughDielectric {
+    pub fn new(ref_idx: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric::new_colored(V3::ones(), ref_idx, roughness)
+    }
+
+    pub fn new_colored(albedo: V3, ref_idx: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric { albedo, ref_idx, ggx: Ggx::new(roughness), absorption: V3::zeros() }
+    }
+
+    /// Light inside is attenuated by `exp(-absorption * distance)`.
+    pub fn with_absorption(self, absorption: V3) -> RoughDielectric {
+        RoughDielectric { absorption, ..self }
+    }
+
+    /// Normal facing the ray and index of refraction relative to its side.
+    fn side(&self, ray: &Ray, hit: &Hit) -> (V3, f64) {
+        if ray.direction.dot(hit.normal) > 0.0 {
+            (-hit.normal, 1.0 / self.ref_idx)
+        } else {
+            (hit.normal, self.ref_idx)
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit) -> Box<dyn PDF> {
+        let (normal, eta) = self.side(ray, hit);
+        Box::new(GgxDielectricPDF::new(&normal, &-ray.direction, eta, self.ggx))
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        let (normal, eta) = self.side(ray, hit);
+        let onb = ONB::from_w(&normal);
+        let wo = onb.project(-ray.direction.unit());
+        let wi = self.ggx.sample_dielectric(wo, eta, next_sample_2d(), next_sample_1d())?;
+        // visible normals sampling and picking by Fresnel leave only masking of `wi` in the weight
+        let weight = self.ggx.g2(wo, wi) / self.ggx.g1(wo);
+        let attenuation = if wi.z < 0.0 { self.albedo } else { V3::ones() };
+        let attenuation = attenuation * transmittance(self.absorption, ray, hit);
+        Some(ray.produce(hit.point, onb.local(wi), weight * attenuation))
+    }
+
+    /// Attenuation is all in `scattering`, so the renderer can weigh samples of mixed PDFs by it.
+    fn scatter_with_pdf(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        Some(Scatter::Diffuse(self.pdf(ray, hit), Color(V3::ones())))
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &Hit, direction: &V3) -> f64 {
+        self.pdf(ray, hit).value(direction, hit)
+    }
+
+    fn scattering(&self, ray: &Ray, hit: &Hit, direction: &V3) -> V3 {
+        let (normal, eta) = self.side(ray, hit);
+        let onb = ONB::from_w(&normal);
+        let (reflected, refracted) = self.ggx.dielectric(
+            onb.project(-ray.direction.unit()),
+            onb.project(direction.unit()),
+            eta,
+        );
+        (V3::all(reflected) + refracted * self.albedo) * transmittance(self.absorption, ray, hit)
+    }
+}
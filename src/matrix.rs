@@ -0,0 +1,237 @@
+use std::f64::consts::PI;
+use std::ops::Mul;
+
+use crate::vec::V3;
+
+/// Row-major 4x4 matrix of an affine transform, applied to column vectors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct M4(pub [[f64; 4]; 4]);
+
+impl M4 {
+    pub const fn identity() -> M4 {
+        M4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: V3) -> M4 {
+        M4([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(factors: V3) -> M4 {
+        M4([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees around `axis` when it points at the viewer.
+    pub fn rotation(axis: V3, angle: f64) -> M4 {
+        let k = axis.unit();
+        let (sin, cos) = f64::sin_cos((PI / 180.0) * angle);
+        let t = 1.0 - cos;
+        M4([
+            [cos + t * k.x * k.x, t * k.x * k.y - sin * k.z, t * k.x * k.z + sin * k.y, 0.0],
+            [t * k.y * k.x + sin * k.z, cos + t * k.y * k.y, t * k.y * k.z - sin * k.x, 0.0],
+            [t * k.z * k.x - sin * k.y, t * k.z * k.y + sin * k.x, cos + t * k.z * k.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transform_point(&self, p: V3) -> V3 {
+        self.transform_vector(p) + V3::new(self.0[0][3], self.0[1][3], self.0[2][3])
+    }
+
+    /// Applies linear part only, as for directions and offsets.
+    pub fn transform_vector(&self, v: V3) -> V3 {
+        let m = &self.0;
+        mul_by_matrix!(v,
+            m[0][0], m[0][1], m[0][2],
+            m[1][0], m[1][1], m[1][2],
+            m[2][0], m[2][1], m[2][2]
+        )
+    }
+
+    /// Applies transposed linear part, normals are transformed by the transposed inverse.
+    pub fn transform_transposed(&self, v: V3) -> V3 {
+        let m = &self.0;
+        mul_by_matrix!(v,
+            m[0][0], m[1][0], m[2][0],
+            m[0][1], m[1][1], m[2][1],
+            m[0][2], m[1][2], m[2][2]
+        )
+    }
+
+    /// Determinant of the linear part, volume scale of the transform.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<M4> {
+        let mut a = self.0;
+        let mut inv = M4::identity().0;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 { return None; }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                if row == col { continue; }
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inv[row][k] -= factor * inv[col][k];
+                }
+            }
+        }
+        Some(M4(inv))
+    }
+}
+
+impl Mul for M4 {
+    type Output = M4;
+
+    fn mul(self, other: M4) -> M4 {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        M4(result)
+    }
+}
+
+/// Unit quaternion of a rotation, interpolated by `slerp` between keyframes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub v: V3,
+}
+
+impl Quat {
+    pub const fn identity() -> Quat {
+        Quat { w: 1.0, v: V3::zeros() }
+    }
+
+    /// Same rotation as `M4::rotation(axis, angle)`.
+    pub fn rotation(axis: V3, angle: f64) -> Quat {
+        let (sin, cos) = f64::sin_cos((PI / 360.0) * angle);
+        Quat { w: cos, v: sin * axis.unit() }
+    }
+
+    fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    /// Radians `other` is rotated by relative to `self`.
+    pub fn angle_to(&self, other: &Quat) -> f64 {
+        2.0 * f64::acos(self.dot(other).abs().min(1.0))
+    }
+
+    /// Constant angular speed along the shorter arc.
+    pub fn slerp(&self, other: &Quat, t: f64) -> Quat {
+        let mut cos = self.dot(other);
+        let other = if cos < 0.0 {
+            cos = -cos;
+            Quat { w: -other.w, v: -other.v }
+        } else { *other };
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (f64::sin((1.0 - t) * theta) / sin, f64::sin(t * theta) / sin)
+        };
+        let w = a * self.w + b * other.w;
+        let v = a * self.v + b * other.v;
+        let norm = f64::sqrt(w * w + v.sqr_length());
+        Quat { w: w / norm, v: v / norm }
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat { w: self.w, v: -self.v }
+    }
+
+    pub fn to_matrix(self) -> M4 {
+        let (w, V3 { x, y, z }) = (self.w, self.v);
+        M4([
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+            [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+            [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+
+    /// Rotation by `other` followed by `self`.
+    fn mul(self, other: Quat) -> Quat {
+        Quat {
+            w: self.w * other.w - self.v.dot(other.v),
+            v: self.w * other.v + other.w * self.v + self.v.cross(other.v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::matrix::{M4, Quat};
+    use crate::vec::V3;
+
+    #[test]
+    fn test_inverse() {
+        let m = M4::translation(V3::new(1.0, -2.0, 3.0))
+            * M4::rotation(V3::new(1.0, 2.0, 3.0), 37.0)
+            * M4::scaling(V3::new(2.0, 0.5, 3.0));
+        let product = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.0[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert!((m.determinant() - 3.0).abs() < 1e-12);
+        assert_eq!(M4::scaling(V3::new(1.0, 0.0, 1.0)).inverse(), None);
+    }
+
+    #[test]
+    fn test_quat_matches_matrix() {
+        let axis = V3::new(-1.0, 2.0, 0.5);
+        let rotation = Quat::rotation(axis, 70.0);
+        let expected = M4::rotation(axis, 70.0);
+        let half = Quat::identity().slerp(&rotation, 0.5);
+        let twice = (half * half).to_matrix();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((rotation.to_matrix().0[i][j] - expected.0[i][j]).abs() < 1e-12);
+                assert!((twice.0[i][j] - expected.0[i][j]).abs() < 1e-12);
+            }
+        }
+        assert!((Quat::identity().angle_to(&rotation) - 70.0f64.to_radians()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::bvh::{BVH, BvhBuilder};
+use crate::hittable::{Hittable, Triangle};
+use crate::material::{Lambertian, Material};
+use crate::texture::Color;
+use crate::vec::V3;
+
+pub use obj::*;
+pub use ply::*;
+
+mod obj;
+mod ply;
+
+/// Puts `triangles` into their own BVH, so the mesh is a single object of the scene.
+pub fn build_mesh(triangles: Vec<Triangle>, bvh: BvhBuilder) -> Result<Box<dyn Hittable>, String> {
+    if triangles.is_empty() {
+        return Err("mesh has no triangles".to_string());
+    }
+    let objs: Vec<Box<dyn Hittable>> = triangles.into_iter()
+        .map(|triangle| Box::new(triangle) as Box<dyn Hittable>)
+        .collect();
+    Ok(BVH::new(objs, bvh))
+}
+
+/// Used for faces nothing else is specified for.
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Color(V3::all(0.73))))
+}
+
+/// Prefixes `msg` with file and line it's about.
+fn error_at(path: &Path, line: usize, msg: impl std::fmt::Display) -> String {
+    format!("{}:{}: {}", path.display(), line, msg)
+}
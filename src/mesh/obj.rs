@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::Arc;
+
+use crate::bvh::BvhBuilder;
+use crate::hittable::{Hittable, Triangle};
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::texture::Color;
+use crate::vec::V3;
+
+use super::{build_mesh, default_material, error_at};
+
+/// Loads Wavefront OBJ mesh with materials from its MTL libraries.
+/// `material` overrides materials of all faces.
+pub fn load_obj(path: &Path, material: Option<Arc<dyn Material>>, bvh: BvhBuilder) -> Result<Box<dyn Hittable>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    build_mesh(parse_obj(&source, path, material)?, bvh)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn parse_obj(source: &str, path: &Path, material: Option<Arc<dyn Material>>) -> Result<Vec<Triangle>, String> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let default_material = material.clone().unwrap_or_else(default_material);
+
+    let mut positions: Vec<V3> = vec![];
+    let mut normals: Vec<V3> = vec![];
+    let mut uvs: Vec<[f64; 2]> = vec![];
+    let mut library: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut current = Arc::clone(&default_material);
+    let mut triangles = vec![];
+
+    for (idx, line) in source.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |msg: String| error_at(path, line_no, msg);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => positions.push(V3::from(parse_floats::<3>(&mut tokens).map_err(err)?)),
+            Some("vn") => normals.push(V3::from(parse_floats::<3>(&mut tokens).map_err(err)?)),
+            Some("vt") => uvs.push(parse_floats::<2>(&mut tokens).map_err(err)?),
+            Some("f") => {
+                let corners = tokens
+                    .map(|corner| parse_corner(corner, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(err)?;
+                if corners.len() < 3 {
+                    return Err(err(format!("face has {} vertices", corners.len())));
+                }
+                // polygons are assumed convex and fanned out from the first vertex
+                for i in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+                    let mut triangle = Triangle::new(
+                        [positions[a.0], positions[b.0], positions[c.0]],
+                        Arc::clone(&current),
+                    );
+                    if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+                        triangle = triangle.with_uvs([uvs[ta], uvs[tb], uvs[tc]]);
+                    }
+                    if let (Some(na), Some(nb), Some(nc)) = (a.2, b.2, c.2) {
+                        triangle = triangle.with_normals([normals[na].unit(), normals[nb].unit(), normals[nc].unit()]);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            Some("mtllib") if material.is_none() => {
+                for name in tokens {
+                    library.extend(load_mtl(&dir.join(name))?);
+                }
+            }
+            Some("usemtl") if material.is_none() => {
+                let name = tokens.next().unwrap_or("");
+                current = library.get(name)
+                    .cloned()
+                    .ok_or_else(|| err(format!("unknown material '{}'", name)))?;
+            }
+            // groups, objects, smoothing groups, lines and comments don't change the mesh
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+/// Zero-based position, texture coordinate and normal indices of `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_corner(corner: &str, positions: usize, uvs: usize, normals: usize) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = corner.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), positions)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, uvs)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, normals)?),
+    };
+    Ok((position, uv, normal))
+}
+
+/// OBJ indices start at 1, negative ones count back from the last element defined so far.
+fn resolve_index(index: &str, len: usize) -> Result<usize, String> {
+    let index: i64 = index.parse().map_err(|_| format!("invalid index '{}'", index))?;
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+    if (0..len as i64).contains(&resolved) {
+        Ok(resolved as usize)
+    } else {
+        Err(format!("index {} is out of range", index))
+    }
+}
+
+fn parse_floats<const N: usize>(tokens: &mut SplitWhitespace) -> Result<[f64; N], String> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        let token = tokens.next().ok_or_else(|| format!("expected {} numbers", N))?;
+        *value = f64::from_str(token).map_err(|_| format!("invalid number '{}'", token))?;
+    }
+    Ok(values)
+}
+
+/// Subset of MTL material parameters we can map onto our materials.
+#[derive(Debug)]
+struct MtlMaterial {
+    diffuse: V3,
+    specular: V3,
+    emission: V3,
+    shininess: f64,
+    ior: f64,
+    dissolve: f64,
+    transmission: Option<V3>,
+    illum: u32,
+    diffuse_map: Option<String>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: V3::all(0.8),
+            specular: V3::zeros(),
+            emission: V3::zeros(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            transmission: None,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Emissive materials become lights, transparent ones glass,
+    /// the ones with specular dominating diffuse metals and the rest Lambertian.
+    fn build(&self, dir: &Path) -> Result<Arc<dyn Material>, String> {
+        if self.emission.sqr_length() > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new(Box::new(Color(self.emission)), 1.0)));
+        }
+        if self.dissolve < 1.0 || [4, 6, 7, 9].contains(&self.illum) {
+            let color = self.transmission.unwrap_or_else(V3::ones);
+            return Ok(Arc::new(Dielectric::new_colored(color, self.ior)));
+        }
+        if self.specular.sqr_length() > 0.0
+            && (self.illum == 3 || max_component(self.specular) >= max_component(self.diffuse)) {
+            // roughness of Blinn-Phong lobe with this exponent
+            let fuzz = f64::sqrt(2.0 / (self.shininess + 2.0));
+            return Ok(Arc::new(Metal::new_fuzzed(self.specular, fuzz)));
+        }
+        match &self.diffuse_map {
+            Some(name) => {
+                let path = dir.join(name);
+                let image = image::open(&path)
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+                Ok(Arc::new(Lambertian::texture(Box::new(image.to_rgb()))))
+            }
+            None => Ok(Arc::new(Lambertian::new(Color(self.diffuse)))),
+        }
+    }
+}
+
+fn max_component(v: V3) -> f64 {
+    v.x.max(v.y).max(v.z)
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut descs: Vec<(String, MtlMaterial)> = vec![];
+    for (idx, line) in source.lines().enumerate() {
+        let err = |msg: String| error_at(path, idx + 1, msg);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            descs.push((tokens.next().unwrap_or("").to_string(), MtlMaterial::default()));
+            continue;
+        }
+        let desc = match descs.last_mut() {
+            Some((_, desc)) => desc,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => desc.diffuse = V3::from(parse_floats::<3>(&mut tokens).map_err(err)?),
+            "Ks" => desc.specular = V3::from(parse_floats::<3>(&mut tokens).map_err(err)?),
+            "Ke" => desc.emission = V3::from(parse_floats::<3>(&mut tokens).map_err(err)?),
+            "Tf" => desc.transmission = Some(V3::from(parse_floats::<3>(&mut tokens).map_err(err)?)),
+            "Ns" => desc.shininess = parse_floats::<1>(&mut tokens).map_err(err)?[0],
+            "Ni" => desc.ior = parse_floats::<1>(&mut tokens).map_err(err)?[0],
+            "d" => desc.dissolve = parse_floats::<1>(&mut tokens).map_err(err)?[0],
+            "Tr" => desc.dissolve = 1.0 - parse_floats::<1>(&mut tokens).map_err(err)?[0],
+            "illum" => desc.illum = parse_floats::<1>(&mut tokens).map_err(err)?[0] as u32,
+            // options of the map go before the file name
+            "map_Kd" => desc.diffuse_map = tokens.last().map(str::to_string),
+            _ => {}
+        }
+    }
+    descs.into_iter()
+        .map(|(name, desc)| desc.build(dir).map(|material| (name, material)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::mesh::obj::parse_obj;
+
+    #[test]
+    fn test_parse_faces() {
+        let source = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/3/1
+f -4//1 -3//1 -2//1
+";
+        let triangles = parse_obj(source, Path::new("quad.obj"), None).unwrap();
+        assert_eq!(triangles.len(), 3);
+
+        let err = parse_obj("v 0 0 0\nf 1 2 3\n", Path::new("bad.obj"), None).err().unwrap();
+        assert_eq!(err, "bad.obj:2: index 2 is out of range");
+    }
+}
@@ -0,0 +1,382 @@
+use std::path::Path;
+use std::str::SplitAsciiWhitespace;
+use std::sync::Arc;
+
+use crate::bvh::BvhBuilder;
+use crate::hittable::{Hittable, Triangle};
+use crate::material::Material;
+use crate::vec::V3;
+
+use super::{build_mesh, default_material};
+
+/// Loads Stanford PLY mesh, ASCII or binary.
+/// Vertex normals give smooth shading, vertex colors become `VertexColors` of a Lambertian
+/// kept inline in each triangle, unless `material` overrides it.
+pub fn load_ply(path: &Path, material: Option<Arc<dyn Material>>, bvh: BvhBuilder) -> Result<Box<dyn Hittable>, String> {
+    let data = std::fs::read(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    parse_ply(&data, material)
+        .and_then(|triangles| build_mesh(triangles, bvh))
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, String> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            other => Err(format!("unknown property type '{}'", other)),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// name, type of the length, type of items
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn index_of(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name()))
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// offset of the first byte after the header
+    body: usize,
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, String> {
+    if !data.starts_with(b"ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let end = find(data, b"end_header")
+        .ok_or_else(|| "header has no end".to_string())?;
+    let mut body = end + b"end_header".len();
+    // header ends with a single line break, \r\n on files written on Windows
+    if data.get(body) == Some(&b'\r') { body += 1; }
+    if data.get(body) == Some(&b'\n') { body += 1; }
+    let header = std::str::from_utf8(&data[..end])
+        .map_err(|_| "header is not ASCII".to_string())?;
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for (idx, line) in header.lines().enumerate() {
+        let err = |msg: String| format!("header line {}: {}", idx + 1, msg);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| err(format!("invalid count '{}'", count)))?,
+                properties: vec![],
+            }),
+            ["property", "list", len, item, name] => elements.last_mut()
+                .ok_or_else(|| err("property outside of element".to_string()))?
+                .properties.push(Property::List(name.to_string(), Scalar::parse(len).map_err(err)?, Scalar::parse(item).map_err(err)?)),
+            ["property", ty, name] => elements.last_mut()
+                .ok_or_else(|| err("property outside of element".to_string()))?
+                .properties.push(Property::Scalar(name.to_string(), Scalar::parse(ty).map_err(err)?)),
+            ["format", ..] => return Err(err(format!("unsupported format '{}'", line))),
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| "header has no format".to_string())?;
+    Ok(Header { format, elements, body })
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Source of property values in file order.
+trait Values {
+    fn next(&mut self, ty: Scalar) -> Result<f64, String>;
+}
+
+struct AsciiValues<'a>(SplitAsciiWhitespace<'a>);
+
+impl Values for AsciiValues<'_> {
+    fn next(&mut self, _: Scalar) -> Result<f64, String> {
+        let token = self.0.next().ok_or_else(|| "unexpected end of data".to_string())?;
+        token.parse().map_err(|_| format!("invalid number '{}'", token))
+    }
+}
+
+struct BinaryValues<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Values for BinaryValues<'_> {
+    fn next(&mut self, ty: Scalar) -> Result<f64, String> {
+        let size = ty.size();
+        if self.data.len() < size {
+            return Err("unexpected end of data".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(bytes);
+        if self.big_endian { buf[..size].reverse(); }
+        let [b0, b1, b2, b3, ..] = buf;
+        Ok(match ty {
+            Scalar::I8 => b0 as i8 as f64,
+            Scalar::U8 => b0 as f64,
+            Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buf),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Vertices {
+    positions: Vec<V3>,
+    normals: Vec<V3>,
+    colors: Vec<V3>,
+    uvs: Vec<[f64; 2]>,
+}
+
+fn parse_ply(data: &[u8], material: Option<Arc<dyn Material>>) -> Result<Vec<Triangle>, String> {
+    let header = parse_header(data)?;
+    let body = &data[header.body..];
+    match header.format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(body).map_err(|_| "body is not ASCII".to_string())?;
+            read_elements(&header.elements, &mut AsciiValues(text.split_ascii_whitespace()), material)
+        }
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => {
+            let mut values = BinaryValues { data: body, big_endian: header.format == Format::BinaryBigEndian };
+            read_elements(&header.elements, &mut values, material)
+        }
+    }
+}
+
+fn read_elements<V: Values>(elements: &[Element], values: &mut V, material: Option<Arc<dyn Material>>) -> Result<Vec<Triangle>, String> {
+    let mut vertices = Vertices::default();
+    let mut triangles = vec![];
+    let default_material = material.clone().unwrap_or_else(default_material);
+    for element in elements {
+        match element.name.as_str() {
+            "vertex" => vertices = read_vertices(element, values)?,
+            "face" => read_faces(element, values, &vertices, &material, &default_material, &mut triangles)?,
+            _ => for _ in 0..element.count {
+                for property in &element.properties {
+                    read_property(property, values)?;
+                }
+            }
+        }
+    }
+    Ok(triangles)
+}
+
+/// Value of a scalar property, for lists their length, items are skipped.
+fn read_property<V: Values>(property: &Property, values: &mut V) -> Result<f64, String> {
+    match property {
+        Property::Scalar(_, ty) => values.next(*ty),
+        Property::List(_, len, item) => {
+            let len = values.next(*len)? as usize;
+            for _ in 0..len { values.next(*item)?; }
+            Ok(len as f64)
+        }
+    }
+}
+
+fn read_vertices<V: Values>(element: &Element, values: &mut V) -> Result<Vertices, String> {
+    let position = [element.index_of(&["x"]), element.index_of(&["y"]), element.index_of(&["z"])];
+    let normal = [element.index_of(&["nx"]), element.index_of(&["ny"]), element.index_of(&["nz"])];
+    let color = [
+        element.index_of(&["red", "r", "diffuse_red"]),
+        element.index_of(&["green", "g", "diffuse_green"]),
+        element.index_of(&["blue", "b", "diffuse_blue"]),
+    ];
+    let uv = [element.index_of(&["u", "s", "texture_u", "texture_s"]), element.index_of(&["v", "t", "texture_v", "texture_t"])];
+    if position.iter().any(Option::is_none) {
+        return Err("vertex has no x, y and z".to_string());
+    }
+    // 8-bit colors are sRGB, same as image textures
+    let color_scale = |idx: usize| match element.properties[idx] {
+        Property::Scalar(_, Scalar::U8) => 1.0 / 255.0,
+        Property::Scalar(_, Scalar::U16) => 1.0 / 65535.0,
+        _ => 1.0,
+    };
+
+    let mut vertices = Vertices::default();
+    vertices.positions.reserve(element.count);
+    let mut row = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in row.iter_mut().zip(&element.properties) {
+            *value = read_property(property, values)?;
+        }
+        let get = |indices: &[Option<usize>; 3]| indices.map(|idx| idx.map_or(0.0, |idx| row[idx]));
+        vertices.positions.push(V3::from(get(&position)));
+        if normal.iter().all(Option::is_some) {
+            vertices.normals.push(V3::from(get(&normal)).unit());
+        }
+        if let [Some(r), Some(g), Some(b)] = color {
+            let [r, g, b] = [r, g, b].map(|idx| (row[idx] * color_scale(idx)).powf(2.2));
+            vertices.colors.push(V3::new(r, g, b));
+        }
+        if let [Some(u), Some(v)] = uv {
+            vertices.uvs.push([row[u], row[v]]);
+        }
+    }
+    Ok(vertices)
+}
+
+fn read_faces<V: Values>(
+    element: &Element,
+    values: &mut V,
+    vertices: &Vertices,
+    material: &Option<Arc<dyn Material>>,
+    default_material: &Arc<dyn Material>,
+    triangles: &mut Vec<Triangle>,
+) -> Result<(), String> {
+    let indices_at = element.index_of(&["vertex_indices", "vertex_index"])
+        .ok_or_else(|| "face has no vertex_indices".to_string())?;
+    let vertex_colors = material.is_none() && !vertices.colors.is_empty();
+    // vertex colors are interpolated by barycentric coordinates, texture coordinates would replace them
+    let uvs = !vertex_colors && !vertices.uvs.is_empty();
+    let smooth = !vertices.normals.is_empty();
+    triangles.reserve(element.count);
+
+    let mut face: Vec<usize> = vec![];
+    for _ in 0..element.count {
+        for (idx, property) in element.properties.iter().enumerate() {
+            match property {
+                Property::List(_, len, item) if idx == indices_at => {
+                    face.clear();
+                    let len = values.next(*len)? as usize;
+                    for _ in 0..len {
+                        let index = values.next(*item)?;
+                        if index < 0.0 || index >= vertices.positions.len() as f64 {
+                            return Err(format!("vertex index {} is out of range", index));
+                        }
+                        face.push(index as usize);
+                    }
+                }
+                _ => { read_property(property, values)?; }
+            }
+        }
+        for i in 1..face.len().saturating_sub(1) {
+            let corners = [face[0], face[i], face[i + 1]];
+            let mut triangle = Triangle::new(corners.map(|idx| vertices.positions[idx]), Arc::clone(default_material));
+            if vertex_colors {
+                triangle = triangle.with_colors(corners.map(|idx| vertices.colors[idx]));
+            }
+            if smooth {
+                triangle = triangle.with_normals(corners.map(|idx| vertices.normals[idx]));
+            }
+            if uvs {
+                triangle = triangle.with_uvs(corners.map(|idx| vertices.uvs[idx]));
+            }
+            triangles.push(triangle);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mesh::ply::parse_ply;
+
+    const HEADER: &str = "ply
+format {}
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    #[test]
+    fn test_ascii_and_binary_agree() {
+        let ascii = HEADER.replace("{}", "ascii 1.0") + "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+        let mut binary = HEADER.replace("{}", "binary_big_endian 1.0").into_bytes();
+        for (x, y, rgb) in &[(0.0f32, 0.0f32, [255u8, 0, 0]), (1.0, 0.0, [0, 255, 0]), (1.0, 1.0, [0, 0, 255]), (0.0, 1.0, [255, 255, 255])] {
+            for coord in &[*x, *y, 0.0] {
+                binary.extend_from_slice(&coord.to_be_bytes());
+            }
+            binary.extend_from_slice(rgb);
+        }
+        binary.push(4);
+        for idx in &[0i32, 1, 2, 3] {
+            binary.extend_from_slice(&idx.to_be_bytes());
+        }
+
+        let ascii = parse_ply(ascii.as_bytes(), None).unwrap();
+        let binary = parse_ply(&binary, None).unwrap();
+        assert_eq!(ascii.len(), 2);
+        assert_eq!(format!("{:?}", ascii), format!("{:?}", binary));
+    }
+
+    #[test]
+    fn test_negative_vertex_index_is_rejected() {
+        let ascii = HEADER.replace("{}", "ascii 1.0") + "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n3 0 -1 2\n";
+        assert_eq!(parse_ply(ascii.as_bytes(), None).err().unwrap(), "vertex index -1 is out of range");
+    }
+}
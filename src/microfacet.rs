@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+use crate::vec::V3;
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals.
+/// Directions are in the local frame of the surface, normal along `z`,
+/// all of them pointing away from the surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// Perceptual `roughness` in `0..1` is squared, as in most material models.
+    pub fn new(roughness: f64) -> Ggx {
+        // perfectly smooth surface makes D a delta, keep it just sharp instead
+        Ggx { alpha: f64::max(roughness * roughness, 1e-4) }
+    }
+
+    /// Density of microfacet normals `h` per projected area.
+    pub fn d(&self, h: V3) -> f64 {
+        if h.z <= 0.0 { return 0.0; }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * t * t)
+    }
+
+    fn lambda(&self, w: V3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 { return f64::INFINITY; }
+        let tan2 = f64::max(1.0 - cos2, 0.0) / cos2;
+        0.5 * (f64::sqrt(1.0 + self.alpha * self.alpha * tan2) - 1.0)
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: V3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g2(&self, wo: V3, wi: V3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of normals `h` visible from `wo`.
+    pub fn visible_d(&self, wo: V3, h: V3) -> f64 {
+        if wo.z <= 0.0 { return 0.0; }
+        self.g1(wo) * f64::max(wo.dot(h), 0.0) * self.d(h) / wo.z
+    }
+
+    /// Normal visible from `wo` sampled by `visible_d` (Heitz 2018).
+    pub fn sample_visible(&self, wo: V3, [u1, u2]: [f64; 2]) -> V3 {
+        // stretch the view so that the distribution becomes a hemisphere
+        let vh = V3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 { V3::new(-vh.y, vh.x, 0.0) / len2.sqrt() } else { V3::new(1.0, 0.0, 0.0) };
+        let t2 = vh.cross(t1);
+
+        // uniform disc with its half hidden by the view direction squeezed
+        let r = u1.sqrt();
+        let (sin, cos) = f64::sin_cos(2.0 * PI * u2);
+        let p1 = r * cos;
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * sin;
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt(f64::max(1.0 - p1 * p1 - p2 * p2, 0.0)) * vh;
+
+        V3::new(self.alpha * nh.x, self.alpha * nh.y, f64::max(nh.z, 0.0)).unit()
+    }
+
+    /// Density of `wi` reflected from `wo` by normals sampled with `sample_visible`.
+    pub fn reflection_pdf(&self, wo: V3, wi: V3) -> f64 {
+        if wi.z <= 0.0 { return 0.0; }
+        let h = (wo + wi).unit();
+        self.visible_d(wo, h) / (4.0 * wo.dot(h))
+    }
+
+    /// Direction of `wo` reflected or refracted by a normal sampled with `sample_visible`,
+    /// picked by its Fresnel reflectance, into medium with `eta` times higher index of refraction
+    /// (below the surface). None when it ends up on the wrong side of the surface.
+    pub fn sample_dielectric(&self, wo: V3, eta: f64, u: [f64; 2], pick: f64) -> Option<V3> {
+        let h = self.sample_visible(wo, u);
+        let cosine = wo.dot(h);
+        match refract(wo, h, eta) {
+            Some(wi) if pick >= fresnel_dielectric(cosine, eta) => Some(wi).filter(|wi| wi.z < 0.0),
+            _ => Some((-wo).reflect(h)).filter(|wi| wi.z > 0.0),
+        }
+    }
+
+    /// Density of `wi` sampled by `sample_dielectric`.
+    pub fn dielectric_pdf(&self, wo: V3, wi: V3, eta: f64) -> f64 {
+        match dielectric_normal(wo, wi, eta) {
+            Some(h) if wi.z > 0.0 =>
+                self.visible_d(wo, h) * fresnel_dielectric(wo.dot(h), eta) / (4.0 * wo.dot(h)),
+            Some(h) => {
+                let denominator = wo.dot(h) + eta * wi.dot(h);
+                self.visible_d(wo, h) * (1.0 - fresnel_dielectric(wo.dot(h), eta))
+                    * eta * eta * -wi.dot(h) / (denominator * denominator)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Rough dielectric BSDF (Walter et al. 2007) times cosine of `wi`,
+    /// separately for reflection and refraction as `(reflected, refracted)`.
+    pub fn dielectric(&self, wo: V3, wi: V3, eta: f64) -> (f64, f64) {
+        match dielectric_normal(wo, wi, eta) {
+            Some(h) if wi.z > 0.0 => {
+                let fresnel = fresnel_dielectric(wo.dot(h), eta);
+                (fresnel * self.d(h) * self.g2(wo, wi) / (4.0 * wo.z), 0.0)
+            }
+            Some(h) => {
+                let fresnel = fresnel_dielectric(wo.dot(h), eta);
+                let denominator = wo.dot(h) + eta * wi.dot(h);
+                // as with the smooth `Dielectric`, radiance is not scaled by squared `eta`
+                let refracted = (1.0 - fresnel) * self.d(h) * self.g2(wo, wi)
+                    * eta * eta * wo.dot(h) * -wi.dot(h) / (wo.z * denominator * denominator);
+                (0.0, refracted)
+            }
+            None => (0.0, 0.0),
+        }
+    }
+}
+
+/// Microfacet normal scattering `wo` into `wi` with relative index of refraction `eta`,
+/// None when no normal facing `wo` does.
+fn dielectric_normal(wo: V3, wi: V3, eta: f64) -> Option<V3> {
+    if wo.z <= 0.0 || wi.z == 0.0 { return None; }
+    let h = if wi.z > 0.0 { wo + wi } else { -(wo + eta * wi) };
+    if h.length() == 0.0 { return None; }
+    let h = h.unit();
+    let h = if h.z < 0.0 { -h } else { h };
+    let valid = wo.dot(h) > 0.0 && if wi.z > 0.0 { wi.dot(h) > 0.0 } else { wi.dot(h) < 0.0 };
+    if valid { Some(h) } else { None }
+}
+
+/// `wo` refracted through surface with normal `h` into medium with `eta` times higher index of refraction,
+/// None on total internal reflection.
+fn refract(wo: V3, h: V3, eta: f64) -> Option<V3> {
+    let cosine = wo.dot(h);
+    let sin2 = (1.0 - cosine * cosine) / (eta * eta);
+    if sin2 >= 1.0 { return None; }
+    Some(-wo / eta + (cosine / eta - f64::sqrt(1.0 - sin2)) * h)
+}
+
+/// Unpolarized reflectance of dielectric, `cosine` of the incident angle,
+/// `eta` is index of refraction behind the surface relative to the one in front of it.
+pub fn fresnel_dielectric(cosine: f64, eta: f64) -> f64 {
+    let sin2 = (1.0 - cosine * cosine) / (eta * eta);
+    if sin2 >= 1.0 { return 1.0; }
+    let cos_t = f64::sqrt(1.0 - sin2);
+    let rs = (cosine - eta * cos_t) / (cosine + eta * cos_t);
+    let rp = (eta * cosine - cos_t) / (eta * cosine + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use crate::microfacet::{fresnel_dielectric, Ggx};
+    use crate::random::{next_std_f64, rand_in_unit_sphere};
+    use crate::vec::V3;
+
+    #[test]
+    fn test_visible_normals_match_pdf() {
+        let wo = V3::new(0.6, -0.2, 0.5).unit();
+        for &roughness in &[0.4, 0.7, 1.0] {
+            let ggx = Ggx::new(roughness);
+            let count = 200_000;
+            // uniform directions over the sphere see both the pdf and the reflectance
+            let (mut pdf, mut reflected) = (0.0, 0.0);
+            let (mut above, mut sampled) = (0.0, 0.0);
+            for _ in 0..count {
+                let wi = rand_in_unit_sphere().unit();
+                pdf += 4.0 * PI * ggx.reflection_pdf(wo, wi);
+                if wi.z > 0.0 {
+                    let h = (wo + wi).unit();
+                    reflected += 4.0 * PI * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z);
+                }
+
+                let h = ggx.sample_visible(wo, [next_std_f64(), next_std_f64()]);
+                let wi = (-wo).reflect(h);
+                if wi.z > 0.0 {
+                    above += 1.0;
+                    sampled += ggx.g2(wo, wi) / ggx.g1(wo);
+                }
+            }
+            let count = count as f64;
+            let (pdf, above, reflected, sampled) = (pdf / count, above / count, reflected / count, sampled / count);
+            // reflections below the surface are lost, more so for rough ones
+            assert!((pdf - above).abs() < 0.02, "{} {} {}", roughness, pdf, above);
+            assert!((reflected - sampled).abs() < 0.02, "{} {} {}", roughness, reflected, sampled);
+        }
+    }
+
+    #[test]
+    fn test_dielectric_matches_pdf() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+
+        // refraction is sharper than reflection, too sharp to be found by uniform directions when smooth
+        let wo = V3::new(0.6, -0.2, 0.5).unit();
+        for &(roughness, eta) in &[(0.5, 1.5), (0.8, 1.5), (0.5, 1.0 / 1.5), (0.8, 1.0 / 1.5)] {
+            let ggx = Ggx::new(roughness);
+            let count = 200_000;
+            let (mut pdf, mut scattered) = (0.0, 0.0);
+            let (mut valid, mut sampled) = (0.0, 0.0);
+            for _ in 0..count {
+                let wi = rand_in_unit_sphere().unit();
+                pdf += 4.0 * PI * ggx.dielectric_pdf(wo, wi, eta);
+                let (reflected, refracted) = ggx.dielectric(wo, wi, eta);
+                scattered += 4.0 * PI * (reflected + refracted);
+
+                let sample = [next_std_f64(), next_std_f64()];
+                if let Some(wi) = ggx.sample_dielectric(wo, eta, sample, next_std_f64()) {
+                    valid += 1.0;
+                    sampled += ggx.g2(wo, wi) / ggx.g1(wo);
+                }
+            }
+            let count = count as f64;
+            let (pdf, valid, scattered, sampled) = (pdf / count, valid / count, scattered / count, sampled / count);
+            assert!((pdf - valid).abs() < 0.02, "{} {} {} {}", roughness, eta, pdf, valid);
+            assert!((scattered - sampled).abs() < 0.02, "{} {} {} {}", roughness, eta, scattered, sampled);
+        }
+    }
+}
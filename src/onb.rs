@@ -19,4 +19,9 @@ impl ONB{
     pub fn local(&self, a: V3) -> V3 {
         a.x * self.u + a.y * self.v + a.z * self.w
     }
+
+    /// Coordinates of `a` in this basis, inverse of `local`.
+    pub fn project(&self, a: V3) -> V3 {
+        V3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
 }
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use image::{ColorType, Rgb};
+use image::hdr::HDREncoder;
+
+use crate::sampler::Postprocessor;
+use crate::vec::V3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    /// 8-bit PNG, postprocessed
+    Png,
+    /// binary P6 PPM, postprocessed
+    Ppm,
+    /// Radiance HDR, unclamped linear radiance
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Result<ImageFormat, String> {
+        let extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            Some(other) => Err(format!("Unsupported output format: '{}'", other)),
+            None => Err(format!("Can't guess output format of '{}'", path.display())),
+        }
+    }
+}
+
+/// Writes `pixels` (row-major, top row first) to `path`,
+/// picking the format by the file extension.
+pub fn save(path: &Path, width: u32, height: u32, pixels: &[V3], postprocess: Postprocessor) -> io::Result<()> {
+    let format = ImageFormat::from_path(path)
+        .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+    match format {
+        ImageFormat::Png => {
+            let bytes = to_bytes(pixels, postprocess);
+            image::save_buffer(path, &bytes, width, height, ColorType::RGB(8))
+        }
+        ImageFormat::Ppm => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write!(out, "P6\n{} {}\n255\n", width, height)?;
+            out.write_all(&to_bytes(pixels, postprocess))?;
+            out.flush()
+        }
+        ImageFormat::Hdr => {
+            let data: Vec<Rgb<f32>> = pixels.iter()
+                .map(|p| Rgb([p.x as f32, p.y as f32, p.z as f32]))
+                .collect();
+            let out = BufWriter::new(File::create(path)?);
+            HDREncoder::new(out).encode(&data, width as usize, height as usize)
+        }
+    }
+}
+
+/// Legacy ASCII P3 PPM, used when no output file is given.
+pub fn print_p3(width: u32, height: u32, pixels: &[V3], postprocess: Postprocessor) {
+    println!("P3");
+    println!("{} {}", width, height);
+    println!("255");
+    for row in pixels.chunks(width as usize) {
+        row.iter()
+            .map(|&pixel| to_rgb8(postprocess(pixel)))
+            .for_each(|[r, g, b]| { print!("{} {} {} ", r, g, b); });
+        println!();
+    }
+}
+
+fn to_bytes(pixels: &[V3], postprocess: Postprocessor) -> Vec<u8> {
+    pixels.iter()
+        .flat_map(|&pixel| to_rgb8(postprocess(pixel)).to_vec())
+        .collect()
+}
+
+fn to_rgb8(color: V3) -> [u8; 3] {
+    let ir: u32 = (255.99 * color.x) as u32;
+    let ig: u32 = (255.99 * color.y) as u32;
+    let ib: u32 = (255.99 * color.z) as u32;
+
+    assert![ir < 256];
+    assert![ig < 256];
+    assert![ib < 256];
+
+    [ir as u8, ig as u8, ib as u8]
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+
+    use image::hdr::HDRDecoder;
+
+    use crate::output::{save, to_bytes, ImageFormat};
+    use crate::vec::V3;
+
+    fn identity(color: V3) -> V3 { color }
+
+    fn pixels() -> Vec<V3> {
+        vec![V3::new(0.0, 0.5, 1.0), V3::new(1.0, 0.25, 0.0), V3::new(0.125, 0.75, 0.5),
+             V3::all(1.0), V3::zeros(), V3::new(0.5, 0.5, 0.0)]
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(ImageFormat::from_path(Path::new("out/render.png")), Ok(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("render.PPM")), Ok(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path(Path::new("render.hdr")), Ok(ImageFormat::Hdr));
+        assert!(ImageFormat::from_path(Path::new("render.jpg")).unwrap_err().contains("'jpg'"));
+        assert!(ImageFormat::from_path(Path::new("render")).is_err());
+        let path = std::env::temp_dir().join("rt_output_unknown.tga");
+        assert!(save(&path, 3, 2, &pixels(), identity).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_ldr_roundtrip() {
+        for name in &["rt_output_roundtrip.png", "rt_output_roundtrip.ppm"] {
+            let path = std::env::temp_dir().join(name);
+            save(&path, 3, 2, &pixels(), identity).unwrap();
+            let loaded = image::open(&path).unwrap().to_rgb();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(loaded.dimensions(), (3, 2));
+            assert_eq!(loaded.into_raw(), to_bytes(&pixels(), identity), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_hdr_roundtrip() {
+        // radiance above 1 must survive unclamped
+        let radiance: Vec<V3> = pixels().iter().map(|&p| 4.0 * p).collect();
+        let path = std::env::temp_dir().join("rt_output_roundtrip.hdr");
+        save(&path, 3, 2, &radiance, identity).unwrap();
+        let decoder = HDRDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let loaded = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), radiance.len());
+        for (pixel, expected) in loaded.iter().zip(radiance) {
+            for (&value, expected) in pixel.0.iter().zip(&[expected.x, expected.y, expected.z]) {
+                assert!((value as f64 - expected).abs() < 0.02, "{} != {}", value, expected);
+            }
+        }
+    }
+}
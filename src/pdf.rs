@@ -1,6 +1,7 @@
 use crate::vec::V3;
 use crate::onb::ONB;
-use crate::random::{rand_cosine_direction, next_std_f32, rand_in_unit_sphere};
+use crate::microfacet::Ggx;
+use crate::random::{rand_cosine_direction, next_sample_1d, next_sample_2d, rand_in_unit_sphere};
 use std::ops::Deref;
 use crate::hittable::{Hittable, Hit};
 use std::fmt::Debug;
@@ -56,6 +57,62 @@ impl PDF for IsotropicPDF {
     }
 }
 
+/// Reflections of `wo` off GGX microfacets visible from it.
+#[derive(Debug, Copy, Clone)]
+pub struct GgxPDF {
+    onb: ONB,
+    /// local to `onb`
+    wo: V3,
+    ggx: Ggx,
+}
+
+impl GgxPDF {
+    pub fn new(normal: &V3, wo: &V3, ggx: Ggx) -> Self {
+        let onb = ONB::from_w(normal);
+        GgxPDF { onb, wo: onb.project(wo.unit()), ggx }
+    }
+}
+
+impl PDF for GgxPDF {
+    fn value(&self, direction: &V3, _: &Hit) -> f64 {
+        self.ggx.reflection_pdf(self.wo, self.onb.project(direction.unit()))
+    }
+
+    fn generate(&self) -> V3 {
+        let h = self.ggx.sample_visible(self.wo, next_sample_2d());
+        self.onb.local((-self.wo).reflect(h))
+    }
+}
+
+/// Reflections and refractions of `wo` off GGX microfacets of a dielectric visible from it.
+#[derive(Debug)]
+pub struct GgxDielectricPDF {
+    onb: ONB,
+    /// local to `onb`
+    wo: V3,
+    eta: f64,
+    ggx: Ggx,
+}
+
+impl GgxDielectricPDF {
+    pub fn new(normal: &V3, wo: &V3, eta: f64, ggx: Ggx) -> Self {
+        let onb = ONB::from_w(normal);
+        GgxDielectricPDF { onb, wo: onb.project(wo.unit()), eta, ggx }
+    }
+}
+
+impl PDF for GgxDielectricPDF {
+    fn value(&self, direction: &V3, _: &Hit) -> f64 {
+        self.ggx.dielectric_pdf(self.wo, self.onb.project(direction.unit()), self.eta)
+    }
+
+    fn generate(&self) -> V3 {
+        // lost samples have no direction and are dropped by the renderer
+        self.ggx.sample_dielectric(self.wo, self.eta, next_sample_2d(), next_sample_1d())
+            .map_or_else(V3::zeros, |wi| self.onb.local(wi))
+    }
+}
+
 #[derive(Debug)]
 pub struct HittablePDF<'a> {
     origin: V3,
@@ -104,7 +161,7 @@ impl<A: PDF, B: PDF> PDF for MixturePDF<A, B> {
     }
 
     fn generate(&self) -> V3 {
-        if next_std_f32() < 0.5 {
+        if next_sample_1d() < 0.5 {
             self.a.generate()
         } else {
             self.b.generate()
@@ -112,6 +169,41 @@ impl<A: PDF, B: PDF> PDF for MixturePDF<A, B> {
     }
 }
 
+/// Mixture of PDFs picked by their weights.
+#[derive(Debug)]
+pub struct WeightedPDF {
+    pdfs: Vec<(f64, Box<dyn PDF>)>,
+}
+
+impl WeightedPDF {
+    /// Weights are normalized, PDFs without weight are left out.
+    pub fn new(pdfs: Vec<(f64, Box<dyn PDF>)>) -> Self {
+        let total: f64 = pdfs.iter().map(|(weight, _)| weight).sum();
+        WeightedPDF {
+            pdfs: pdfs.into_iter()
+                .filter(|(weight, _)| *weight > 0.0)
+                .map(|(weight, pdf)| (weight / total, pdf))
+                .collect()
+        }
+    }
+}
+
+impl PDF for WeightedPDF {
+    fn value(&self, direction: &V3, hit: &Hit) -> f64 {
+        self.pdfs.iter().map(|(weight, pdf)| weight * pdf.value(direction, hit)).sum()
+    }
+
+    fn generate(&self) -> V3 {
+        let mut pick = next_sample_1d();
+        for (weight, pdf) in &self.pdfs {
+            if pick < *weight { return pdf.generate(); }
+            pick -= weight;
+        }
+        // rounding left the last one out
+        self.pdfs.last().map_or_else(V3::zeros, |(_, pdf)| pdf.generate())
+    }
+}
+
 impl<T: Deref<Target = dyn PDF>> PDF for T where T: Debug {
     fn value(&self, direction: &V3, hit: &Hit) -> f64 {
         (**self).value(direction, hit)
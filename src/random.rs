@@ -1,15 +1,16 @@
 #[allow(dead_code)]
 
 use std::borrow::BorrowMut;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::ops::Range;
 
 use rand::{RngCore, SeedableRng};
 use rand::distributions::{Distribution, Standard};
 use rand::seq::SliceRandom;
-use rand_distr::{UnitDisc, UnitSphere};
+use rand_distr::UnitSphere;
 use rand_xoshiro::Xoshiro256Plus;
 
+use crate::sequence::{Independent, Sequence, SequenceImpl};
 use crate::vec::{Axis, V3};
 
 thread_local! {
@@ -17,6 +18,96 @@ thread_local! {
             RefCell::new(Xoshiro256Plus::seed_from_u64(0))
 }
 
+/// Restarts the thread's random stream at the state derived from `(seed, pixel, sample)`,
+/// so that a sample doesn't depend on which thread renders it or what it rendered before.
+pub fn reseed(seed: u64, pixel: u64, sample: u64) {
+    let state = mix(mix(mix(seed) ^ pixel) ^ sample);
+    RND.with(|rnd_cell| *rnd_cell.borrow_mut() = Xoshiro256Plus::seed_from_u64(state))
+}
+
+/// SplitMix64 finalizer
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// 2D sample dimensions used by camera: pixel jitter, lens and time.
+const CAMERA_DIMENSIONS: u32 = 3;
+/// 2D sample dimensions reserved for every path vertex, the rest are independent random.
+const VERTEX_DIMENSIONS: u32 = 4;
+
+/// Position in the sample sequence of the current camera path.
+#[derive(Copy, Clone)]
+struct Stream {
+    sequence: SequenceImpl,
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    vertex: u32,
+    dimension: u32,
+    limit: u32,
+}
+
+thread_local! {
+    static STREAM: Cell<Stream> = const {
+        Cell::new(Stream {
+            sequence: SequenceImpl::Independent(Independent),
+            seed: 0,
+            pixel: 0,
+            index: 0,
+            vertex: 0,
+            dimension: 0,
+            limit: 0,
+        })
+    }
+}
+
+/// Starts `index`-th camera path through `pixel`, see `reseed`.
+/// Camera and BSDF dimensions come from `sequence`, everything else from the reseeded RNG.
+pub fn start_sample(sequence: SequenceImpl, seed: u64, pixel: u64, index: u64) {
+    reseed(seed, pixel, index);
+    STREAM.with(|stream| stream.set(Stream {
+        sequence,
+        seed,
+        pixel,
+        index,
+        vertex: 0,
+        dimension: 0,
+        limit: CAMERA_DIMENSIONS,
+    }));
+}
+
+/// Moves to dimensions of the next path vertex, so that the same dimension
+/// serves the same purpose across samples, no matter how many were drawn before.
+pub fn start_vertex() {
+    STREAM.with(|cell| {
+        let stream = cell.get();
+        let dimension = CAMERA_DIMENSIONS + stream.vertex * VERTEX_DIMENSIONS;
+        cell.set(Stream {
+            vertex: stream.vertex + 1,
+            dimension,
+            limit: dimension + VERTEX_DIMENSIONS,
+            ..stream
+        })
+    });
+}
+
+pub fn next_sample_2d() -> [f64; 2] {
+    STREAM.with(|cell| {
+        let stream = cell.get();
+        cell.set(Stream { dimension: stream.dimension + 1, ..stream });
+        if stream.dimension < stream.limit {
+            stream.sequence.sample_2d(stream.seed, stream.pixel, stream.index, stream.dimension)
+        } else { None }
+    }).unwrap_or_else(|| [next_std_f64(), next_std_f64()])
+}
+
+pub fn next_sample_1d() -> f64 {
+    next_sample_2d()[0]
+}
+
 pub fn next_std_f64() -> f64 {
     RND.with(|rnd_cell|
         Standard.sample((*rnd_cell.borrow_mut()).borrow_mut()))
@@ -83,8 +174,7 @@ pub fn rand_in_unit_hemisphere(normal: &V3) -> V3 {
 }
 
 pub fn rand_cosine_direction() -> V3 {
-    let r1 = next_std_f64();
-    let r2 = next_std_f64();
+    let [r1, r2] = next_sample_2d();
     let z = f64::sqrt(1.0 - r2);
 
     let phi = r1 * 2.0 * core::f64::consts::PI;
@@ -99,10 +189,46 @@ pub fn rand_cosine_direction() -> V3 {
 }
 
 pub fn rand_in_unit_disc() -> [f64; 2] {
-    RND.with(|rnd_cell| UnitDisc.sample((*rnd_cell.borrow_mut()).borrow_mut()))
+    to_unit_disc(next_sample_2d())
+}
+
+/// Shirley-Chiu concentric mapping, keeps stratification of the square.
+fn to_unit_disc([u, v]: [f64; 2]) -> [f64; 2] {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 { return [0.0, 0.0]; }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, core::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, core::f64::consts::FRAC_PI_2 - core::f64::consts::FRAC_PI_4 * (a / b))
+    };
+    let (sin, cos) = theta.sin_cos();
+    [r * cos, r * sin]
+}
+
+/// Generator seeded by `name`, for scene content that mustn't depend on the order it's built in.
+pub fn named_rng(name: &str) -> Xoshiro256Plus {
+    Xoshiro256Plus::seed_from_u64(name.bytes().fold(0, |hash, byte| mix(hash ^ byte as u64)))
 }
 
 pub fn with_rnd<T, F>(op: F) -> T
     where F: FnOnce(&mut dyn RngCore) -> T {
     RND.with(|rnd_cell| op((*rnd_cell.borrow_mut()).borrow_mut()))
 }
+
+#[cfg(test)]
+mod test {
+    use crate::random::{reseed, next_std_f64};
+
+    #[test]
+    fn test_reseed_is_reproducible() {
+        reseed(1, 42, 7);
+        let first: Vec<f64> = (0..8).map(|_| next_std_f64()).collect();
+        reseed(1, 42, 8);
+        let other: Vec<f64> = (0..8).map(|_| next_std_f64()).collect();
+        reseed(1, 42, 7);
+        let again: Vec<f64> = (0..8).map(|_| next_std_f64()).collect();
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+}
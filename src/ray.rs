@@ -7,15 +7,21 @@ pub struct Ray {
     pub attenuation: V3,
     pub time: f32,
     pub ttl: i32,
+    /// Nanometers, once the path is split by dispersion
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
     pub fn new(origin: V3, direction: V3, attenuation: V3, time: f32, ttl: i32) -> Ray {
-        Ray { origin, direction, attenuation, time, ttl }
+        Ray { origin, direction, attenuation, time, ttl, wavelength: None }
     }
 
     pub fn produce(self, origin: V3, direction: V3, attenuation: V3) -> Ray {
-        Ray::new(origin, direction, attenuation, self.time, self.ttl - 1)
+        Ray { wavelength: self.wavelength, ..Ray::new(origin, direction, attenuation, self.time, self.ttl - 1) }
+    }
+
+    pub fn with_wavelength(self, wavelength: f64) -> Ray {
+        Ray { wavelength: Some(wavelength), ..self }
     }
 
     pub fn validate(self) -> Option<Ray> {
@@ -1,4 +1,5 @@
 use super::{Hittable, Ray, Renderer, V3};
+use crate::random;
 use std::borrow::Borrow;
 use crate::texture::Color;
 use crate::pdf::{PDF, HittablePDF, MixturePDF};
@@ -15,6 +16,7 @@ impl Renderer for RgbRenderer {
     fn color(&self, r: &Ray) -> V3 {
         match self.hittable.hit(&r, 0.0001, 99999.0) {
             Some(hit) => {
+                random::start_vertex();
                 let emitted = if hit.normal.dot(r.direction.unit()) < 0.0 {
                     hit.material.emmit(&hit).0
                 } else {
@@ -45,9 +47,8 @@ impl Renderer for RgbRenderer {
 
 impl RgbRenderer {
     fn biased_diffuse<'a>(&self, r: &Ray, hit: &Hit, attenuation: Color, mat_pdf: Box<dyn PDF>) -> V3 {
-        let mat_dir = mat_pdf.generate();  // unbiased sample, just in case we need it
         let pdf = MixturePDF::new(
-            mat_pdf,
+            &*mat_pdf,
             HittablePDF::new(hit.point, &self.important)
         );
         if let Some(mut scattered) = r.produce(
@@ -56,15 +57,21 @@ impl RgbRenderer {
             attenuation.0,
         ).validate() {
             let pdf_value = pdf.value(&scattered.direction, &hit);
-            let spdf = hit.material.scattering_pdf(&hit, &scattered.direction);
+            let spdf = hit.material.scattering(r, &hit, &scattered.direction);
             let mut weight = spdf / pdf_value;
-            if weight.is_nan() {
+            if !weight.is_finite() {
                 // coin toss of mixture PDF gave us ray from non-overlapping part of importance PDF,
                 // and weighted probability of hitting that important object is zero too or NaN,
-                // so we get NaN weight. Let's scatter light unbiased, by material PDF, this will
-                // also give us pdf_value = spdf, since they are from same material, so weight is 1.
-                weight = 1.0;
+                // so we get NaN or infinite weight. Let's scatter light unbiased, by material PDF, this will
+                // also give us pdf_value = spdf for materials sampled exactly, so weight is 1.
+                // Drawn only now, so the mixture keeps the first, best distributed dimensions of the vertex.
+                let mat_dir = mat_pdf.generate();
+                weight = hit.material.scattering(r, hit, &mat_dir) / mat_pdf.value(&mat_dir, hit);
                 scattered.direction = mat_dir;
+                if !weight.is_finite() {
+                    // material sampled direction it doesn't scatter to, e.g. below microfacet surface
+                    return V3::zeros();
+                }
             }
             let scattered_color = self.color(&scattered);
             // let scattered_color = 0.5 * scattered.direction.unit() + 0.5; // scatter direction
@@ -1,4 +1,5 @@
 use super::{Hittable, Ray, Renderer, V3};
+use crate::random;
 use std::borrow::Borrow;
 
 pub struct RgbRendererUnbiased {
@@ -10,6 +11,7 @@ impl Renderer for RgbRendererUnbiased {
     fn color(&self, r: &Ray) -> V3 {
         match self.hittable.hit(&r, 0.0001, 99999.0) {
             Some(hit) => {
+                random::start_vertex();
                 let emitted = hit.material.emmit(&hit);
                 return match hit
                     .material
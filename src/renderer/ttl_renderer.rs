@@ -1,4 +1,5 @@
 use super::{Hittable, Ray, Renderer, V3};
+use crate::random;
 
 #[allow(dead_code)]
 pub struct TtlRenderer{
@@ -9,6 +10,7 @@ impl Renderer for TtlRenderer {
     fn color(&self, r: &Ray) -> V3 {
         match self.hittable.hit(&r, 0.0001, 99999.0) {
             Some(hit) => {
+                random::start_vertex();
                 return match hit
                     .material
                     .scatter(r, &hit)
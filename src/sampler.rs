@@ -1,50 +1,306 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::film::{Estimate, Film, Source, Tile};
 use crate::scenes::Scene;
+use crate::sequence::SequenceImpl;
 use crate::vec::V3;
-use crate::{random, clamp};
+use crate::{random, output};
 use rayon::prelude::*;
 
 pub type Postprocessor = fn(V3) -> V3;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Sampler {
     pub width: u32,
     pub height: u32,
+    /// Samples per pixel, upper bound when sampling adaptively
     pub samples: usize,
     pub max_ray_bounces: i32,
     pub pixel_postprocessor: Postprocessor,
+    /// Image file to write, ASCII PPM goes to stdout if not set
+    pub output: Option<PathBuf>,
+    /// Side of the square tiles scheduled across threads
+    pub tile_size: u32,
+    /// Samples per pixel added to the whole image in one pass
+    pub pass_samples: usize,
+    /// How often to write the partially rendered image to `output`
+    pub progress_interval: Option<Duration>,
+    /// Pixel stops sampling when relative error of its mean gets below threshold
+    pub adaptive_threshold: Option<f64>,
+    /// Samples every pixel gets before adaptive sampling may stop it
+    pub min_samples: usize,
+    /// Image of samples spent per pixel
+    pub heatmap: Option<PathBuf>,
+    /// Random streams are derived from seed, pixel and sample index,
+    /// so the same seed gives the same image regardless of threads
+    pub seed: u64,
+    /// Source of camera and BSDF sample dimensions
+    pub sequence: SequenceImpl,
+    /// Raw accumulated radiance, written periodically and when done
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    /// Checkpoint to continue sampling from
+    pub resume: Option<PathBuf>,
 }
 
 impl Sampler {
-    pub fn do_render(self, scene: Scene) -> () {
-        println!("P3");
-        println!("{} {}", self.width, self.height);
-        println!("255");
-        for j in (0..self.height).rev() {
-            let scale = self.samples as f64;
-            let aa = self.samples;
-            let row : Vec<(u32,u32,u32)> = (0..self.width).into_par_iter().map(|i| {
-                let col: V3 = (0..aa).map(|_| {
-                    // let col: V3 = rayon::iter::repeatn((), self.samples).map(|_| {
-                    let [du, dv] = random::rand_in_unit_disc();
-                    let u = (i as f64 + du) / (self.width as f64);
-                    let v = (j as f64 + dv) / (self.height as f64);
-                    scene.color(u, v)
-                }).sum();
-
-                let col = (self.pixel_postprocessor)(col / scale);
-
-                let ir: u32 = (255.99 * col.x) as u32;
-                let ig: u32 = (255.99 * col.y) as u32;
-                let ib: u32 = (255.99 * col.z) as u32;
-
-                assert![ir < 256];
-                assert![ig < 256];
-                assert![ib < 256];
-
-                (ir, ig, ib)
-            }).collect();
-            row.iter().for_each(|(ir, ig, ib)| { print!("{} {} {} ", ir, ig, ib); });
-            println!();
+    pub fn do_render(&self, scene: &Scene) -> std::io::Result<()> {
+        let mut film = match &self.resume {
+            Some(path) => {
+                let film = Film::load(path)?;
+                if (film.width, film.height) != (self.width, self.height) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!(
+                        "Checkpoint is {}x{}, but image is {}x{}",
+                        film.width, film.height, self.width, self.height)));
+                }
+                film
+            }
+            None => Film::new(self.width, self.height),
+        };
+        film.add_source(Source { seed: self.seed, sequence: self.sequence.sequence_type() });
+        let film = self.render(scene, film);
+        self.write(&film)
+    }
+
+    /// Same settings writing to files numbered by `frame`.
+    pub fn frame(&self, frame: u32) -> Sampler {
+        Sampler {
+            output: self.output.as_deref().map(|path| numbered_path(path, frame)),
+            heatmap: self.heatmap.as_deref().map(|path| numbered_path(path, frame)),
+            checkpoint: self.checkpoint.as_deref().map(|path| numbered_path(path, frame)),
+            ..self.clone()
+        }
+    }
+
+    /// Combines checkpoints of independent renders, e.g. made with different seeds on different machines.
+    pub fn merge(self, checkpoints: &[PathBuf]) -> std::io::Result<()> {
+        let mut film: Option<Film> = None;
+        for path in checkpoints {
+            let other = Film::load(path)?;
+            match &mut film {
+                Some(film) => film.merge(&other)
+                    .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg))?,
+                None => film = Some(other),
+            }
+        }
+        match film {
+            Some(film) => self.write(&film),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&self, film: &Film) -> std::io::Result<()> {
+        if let Some(path) = &self.checkpoint {
+            replace_checkpoint(path, film)?;
+        }
+        if let Some(path) = &self.heatmap {
+            output::save(path, film.width, film.height, &film.heatmap(), |c| c)?;
+        }
+        let pixels = film.pixels();
+        match &self.output {
+            Some(path) => output::save(path, film.width, film.height, &pixels, self.pixel_postprocessor),
+            None => {
+                output::print_p3(film.width, film.height, &pixels, self.pixel_postprocessor);
+                Ok(())
+            }
+        }
+    }
+
+    /// Renders the image in passes of `pass_samples`, each pass goes over all tiles in parallel,
+    /// so every pixel converges at the same pace and partial images are meaningful.
+    /// Passes continue until no pixel needs more samples.
+    /// Sample indices continue from counts already in `film`, so resumed samples are fresh.
+    pub fn render(&self, scene: &Scene, film: Film) -> Film {
+        let film = Mutex::new(film);
+        let tiles = Tile::split(self.width, self.height, self.tile_size);
+        let last_snapshot = Mutex::new(Instant::now());
+        let last_checkpoint = Mutex::new(Instant::now());
+
+        for pass in 1.. {
+            let active = AtomicUsize::new(0);
+            tiles.par_iter().for_each(|tile| {
+                let budget: Vec<Range<u32>> = {
+                    let film = film.lock().unwrap();
+                    tile.pixels().map(|(x, y)| self.budget(film.estimate(x, y))).collect()
+                };
+                let sampled = budget.iter().filter(|samples| !samples.is_empty()).count();
+                if sampled == 0 { return; }
+                active.fetch_add(sampled, Ordering::Relaxed);
+
+                let estimates = self.render_tile(scene, tile, &budget);
+                let (pixels, checkpoint) = {
+                    let mut film = film.lock().unwrap();
+                    film.add_tile(tile, &estimates);
+                    let pixels = if self.snapshot_due(&last_snapshot) { Some(film.pixels()) } else { None };
+                    let checkpoint = if self.checkpoint_due(&last_checkpoint) { Some(film.clone()) } else { None };
+                    (pixels, checkpoint)
+                };
+                if let Some(pixels) = pixels {
+                    self.snapshot(&pixels);
+                }
+                if let Some(film) = checkpoint {
+                    self.save_checkpoint(&film);
+                }
+            });
+            let active = active.into_inner();
+            if active == 0 { break; }
+            eprintln!("Pass {}: sampled {} pixels", pass, active);
+        }
+        film.into_inner().unwrap()
+    }
+
+    /// Indices of samples pixel gets in the next pass.
+    fn budget(&self, estimate: &Estimate) -> Range<u32> {
+        let done = estimate.samples;
+        let max = self.samples as u32;
+        if done >= max { return done..done; }
+        if let Some(threshold) = self.adaptive_threshold {
+            if done >= self.min_samples as u32 && estimate.relative_error() < threshold {
+                return done..done;
+            }
+        }
+        done..u32::min(done + self.pass_samples.max(1) as u32, max)
+    }
+
+    fn render_tile(&self, scene: &Scene, tile: &Tile, budget: &[Range<u32>]) -> Vec<Estimate> {
+        tile.pixels().zip(budget).map(|((x, y), samples)| {
+            let i = x;
+            let j = self.height - 1 - y;
+            let pixel = (y * self.width + x) as u64;
+            let mut estimate = Estimate::empty();
+            for sample in samples.clone() {
+                random::start_sample(self.sequence, self.seed, pixel, sample as u64);
+                let [du, dv] = random::rand_in_unit_disc();
+                let u = (i as f64 + du) / (self.width as f64);
+                let v = (j as f64 + dv) / (self.height as f64);
+                estimate.add_sample(scene.color(u, v));
+            }
+            estimate
+        }).collect()
+    }
+
+    fn snapshot_due(&self, last_snapshot: &Mutex<Instant>) -> bool {
+        match (&self.output, self.progress_interval) {
+            (Some(_), Some(interval)) => elapsed(last_snapshot, interval),
+            _ => false
         }
     }
+
+    fn checkpoint_due(&self, last_checkpoint: &Mutex<Instant>) -> bool {
+        self.checkpoint.is_some() && elapsed(last_checkpoint, self.checkpoint_interval)
+    }
+
+    /// Same write-and-move as `snapshot`, errors don't stop the render.
+    fn save_checkpoint(&self, film: &Film) {
+        if let Some(path) = &self.checkpoint {
+            if let Err(err) = replace_checkpoint(path, film) {
+                eprintln!("Failed to write checkpoint: {}", err);
+            }
+        }
+    }
+
+    /// Writes partial image next to the output and moves it over,
+    /// so viewers never pick up a half-written file.
+    fn snapshot(&self, pixels: &[V3]) {
+        if let Some(path) = &self.output {
+            let partial = partial_path(path);
+            let result = output::save(&partial, self.width, self.height, pixels, self.pixel_postprocessor)
+                .and_then(|_| std::fs::rename(&partial, path));
+            if let Err(err) = result {
+                eprintln!("Failed to write partial image: {}", err);
+            }
+        }
+    }
+}
+
+/// Checks if `interval` passed since `last` and restarts it.
+fn elapsed(last: &Mutex<Instant>, interval: Duration) -> bool {
+    let mut last = last.lock().unwrap();
+    if last.elapsed() >= interval {
+        *last = Instant::now();
+        true
+    } else { false }
+}
+
+/// Saves the film next to `path` and moves it over, keeping the previous checkpoint if that fails.
+fn replace_checkpoint(path: &Path, film: &Film) -> std::io::Result<()> {
+    let partial = partial_path(path);
+    film.save(&partial)?;
+    std::fs::rename(&partial, path)
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let extension = path.extension()
+        .map(|ext| format!("partial.{}", ext.to_string_lossy()))
+        .unwrap_or_else(|| "partial".to_string());
+    path.with_extension(extension)
+}
+
+/// Replaces the last run of `#` in the file name with zero-padded `frame`,
+/// or appends it to the file stem as `_0001` when there's none.
+fn numbered_path(path: &Path, frame: u32) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match name.rfind('#') {
+        Some(end) => {
+            let start = name[..end].trim_end_matches('#').len();
+            format!("{}{:0width$}{}", &name[..start], frame, &name[end + 1..], width = end + 1 - start)
+        }
+        None => {
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            match path.extension() {
+                Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+                None => format!("{}_{:04}", stem, frame),
+            }
+        }
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::film::Film;
+    use crate::renderer::RendererType;
+    use crate::sampler::Sampler;
+    use crate::scenes::{cornel_box_with_is, SceneParams};
+    use crate::sequence::{SequenceImpl, SequenceType};
+
+    #[test]
+    fn test_render_is_independent_of_thread_count() {
+        let (width, height, samples) = (12, 12, 8);
+        let scene = cornel_box_with_is(RendererType::RGBBiased,
+                                       SceneParams { nx: width, ny: height, t_off: 0.0, t_span: 0.2, ttl: 8 });
+        let sampler = Sampler {
+            width, height, samples,
+            max_ray_bounces: 8,
+            pixel_postprocessor: |c| c,
+            output: None,
+            tile_size: 5,
+            pass_samples: 3,
+            progress_interval: None,
+            adaptive_threshold: Some(0.2),
+            min_samples: 4,
+            heatmap: None,
+            seed: 11,
+            sequence: SequenceImpl::pick(SequenceType::Sobol, samples),
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(300),
+            resume: None,
+        };
+        let render = |threads: usize| rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build().unwrap()
+            .install(|| sampler.render(&scene, Film::new(width, height)));
+
+        let single = render(1);
+        let multi = render(4);
+        assert!(single.pixels().iter().any(|p| p.x > 0.0));
+        assert_eq!(single.pixels(), multi.pixels());
+        assert_eq!(single.heatmap(), multi.heatmap());
+    }
 }
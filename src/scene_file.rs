@@ -0,0 +1,939 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::bvh::{BVH, BvhBuilder};
+use crate::camera::{Aperture, ApertureImage, Bokeh, CameraKeyframe, CameraPath, Lens, Projection, Shutter};
+use crate::hittable::{AABox, AnimateOp, ConstantMedium, FlipNormalsOp, Hittable, HittableList, Keyframe, MovingSphere, NoHit, Sphere, Transform, TransformOp, XYRect, XZRect, YZRect};
+use crate::material::{absorption_for, Conductor, Dielectric, DiffuseLight, Ior, Lambertian, Material, Metal, Principled, RoughDielectric};
+use crate::matrix::M4;
+use crate::mesh::{load_obj, load_ply};
+use crate::noise::Perlin;
+use crate::random::named_rng;
+use crate::ray::Ray;
+use crate::renderer::{RendererImpl, RendererType};
+use crate::scenes::{self, Scene, SceneParams};
+use crate::texture::{Checker, Color, PerlinTexture, Texture};
+use crate::vec::V3;
+
+/// Scene description, see `scenes/cornel_box.toml` for an example.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    miss: MissShader,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    objects: Vec<Spanned<ObjectDesc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    from: [f64; 3],
+    at: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f32,
+    /// distance from `from` to `at` if not set
+    focus_distance: Option<f64>,
+    #[serde(default)]
+    projection: ProjectionDesc,
+    /// photographic settings replacing `vfov` and `aperture`
+    lens: Option<LensDesc>,
+    bokeh: Option<BokehDesc>,
+    /// camera animation, parameters not set in a keyframe stay as in the previous one
+    #[serde(default)]
+    keyframes: Vec<CameraKeyframeDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LensDesc {
+    focal_length: f64,
+    f_stop: f64,
+    shutter: f32,
+    #[serde(default = "default_iso")]
+    iso: f64,
+    #[serde(default = "default_sensor_height")]
+    sensor_height: f64,
+    /// meters per scene unit
+    #[serde(default = "default_unit")]
+    unit: f64,
+}
+
+fn default_iso() -> f64 { 100.0 }
+
+fn default_sensor_height() -> f64 { 24.0 }
+
+fn default_unit() -> f64 { 1.0 }
+
+#[derive(Debug, Deserialize)]
+struct BokehDesc {
+    #[serde(flatten)]
+    aperture: ApertureDesc,
+    #[serde(default)]
+    cat_eye: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+enum ApertureDesc {
+    Disc,
+    Polygon {
+        blades: u32,
+        #[serde(default)]
+        rotation: f64,
+    },
+    Image { path: String },
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProjectionDesc {
+    #[default]
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+fn default_up() -> [f64; 3] { [0.0, 1.0, 0.0] }
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKeyframeDesc {
+    time: Spanned<f32>,
+    from: Option<[f64; 3]>,
+    at: Option<[f64; 3]>,
+    up: Option<[f64; 3]>,
+    vfov: Option<f64>,
+    aperture: Option<f32>,
+    focus_distance: Option<f64>,
+}
+
+impl CameraDesc {
+    fn path(&self, loader: &Loader) -> Result<CameraPath, String> {
+        let lens = self.lens.as_ref().map(|lens| Lens {
+            focal_length: lens.focal_length,
+            f_stop: lens.f_stop,
+            shutter: lens.shutter,
+            iso: lens.iso,
+            sensor_height: lens.sensor_height,
+            unit: lens.unit,
+        });
+        let bokeh = match &self.bokeh {
+            Some(bokeh) => Bokeh { aperture: bokeh.aperture.build(loader.dir)?, cat_eye: bokeh.cat_eye },
+            None => Bokeh::default(),
+        };
+        let projection = match self.projection {
+            ProjectionDesc::Perspective => Projection::Perspective,
+            ProjectionDesc::Orthographic => Projection::Orthographic,
+            ProjectionDesc::Fisheye => Projection::Fisheye,
+            ProjectionDesc::Equirectangular => Projection::Equirectangular,
+        };
+        let keyframe = |time: f32, from: [f64; 3], at: [f64; 3], up, vfov, aperture, focus_distance: Option<f64>| {
+            let (from, at) = (V3::from(from), V3::from(at));
+            CameraKeyframe {
+                time, from, at,
+                up: V3::from(up),
+                vfov, aperture,
+                focus_distance: focus_distance.unwrap_or_else(|| (from - at).length()),
+            }
+        };
+        if self.keyframes.is_empty() {
+            return Ok(CameraPath::new(vec![
+                keyframe(0.0, self.from, self.at, self.up, self.vfov, self.aperture, self.focus_distance)
+            ]).with_projection(projection).with_bokeh(bokeh).with_lens(lens));
+        }
+        if let Some(desc) = self.keyframes.iter().find(|desc| !desc.time.get_ref().is_finite()) {
+            return Err(loader.error(desc.time.span(), "keyframe `time` must be finite".to_string()));
+        }
+        let mut descs: Vec<&CameraKeyframeDesc> = self.keyframes.iter().collect();
+        descs.sort_by(|a, b| a.time.get_ref().total_cmp(b.time.get_ref()));
+        let (mut from, mut at, mut up) = (self.from, self.at, self.up);
+        let (mut vfov, mut aperture, mut focus_distance) = (self.vfov, self.aperture, self.focus_distance);
+        let keyframes = descs.into_iter().map(|desc| {
+            from = desc.from.unwrap_or(from);
+            at = desc.at.unwrap_or(at);
+            up = desc.up.unwrap_or(up);
+            vfov = desc.vfov.unwrap_or(vfov);
+            aperture = desc.aperture.unwrap_or(aperture);
+            focus_distance = desc.focus_distance.or(focus_distance);
+            keyframe(*desc.time.get_ref(), from, at, up, vfov, aperture, focus_distance)
+        }).collect();
+        Ok(CameraPath::new(keyframes).with_projection(projection).with_bokeh(bokeh).with_lens(lens))
+    }
+}
+
+impl ApertureDesc {
+    fn build(&self, dir: &Path) -> Result<Aperture, String> {
+        match self {
+            ApertureDesc::Disc => Ok(Aperture::Disc),
+            ApertureDesc::Polygon { blades, .. } if *blades < 3 => Err("aperture needs at least 3 blades".to_string()),
+            ApertureDesc::Polygon { blades, rotation } => Ok(Aperture::Polygon { blades: *blades, rotation: *rotation }),
+            ApertureDesc::Image { path } => {
+                let image = image::open(dir.join(path))
+                    .map_err(|err| format!("can't load aperture '{}': {}", path, err))?;
+                ApertureImage::new(&image.to_rgb())
+                    .map(|image| Aperture::Image(Arc::new(image)))
+                    .ok_or_else(|| format!("aperture '{}' is black", path))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MissShader {
+    Sky,
+    #[default]
+    Black,
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Color { color: [f64; 3] },
+    Checker { even: [f64; 3], odd: [f64; 3], step: f64 },
+    Image { path: String },
+    Perlin {
+        scale: f64,
+        #[serde(default)]
+        pattern: PerlinPattern,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PerlinPattern {
+    #[default]
+    Noise,
+    Turbulence,
+    Marble,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        color: Option<[f64; 3]>,
+        texture: Option<String>,
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    /// GGX microfacet metal, either a `metal` preset or complex index of refraction `eta` and `k`
+    Conductor {
+        metal: Option<MetalPreset>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Smooth glass unless `roughness` is given, absorbing either by `absorption` coefficient
+    /// or turning `color_at_distance` after `distance`, `dispersion` replaces `ref_idx`
+    Dielectric {
+        ref_idx: Option<f64>,
+        dispersion: Option<IorDesc>,
+        color: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+        absorption: Option<[f64; 3]>,
+        color_at_distance: Option<[f64; 3]>,
+        #[serde(default = "default_distance")]
+        distance: f64,
+    },
+    DiffuseLight {
+        color: Option<[f64; 3]>,
+        texture: Option<String>,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    /// Disney's principled BSDF, each parameter a number, a color or a texture name
+    Principled {
+        base_color: Option<ParamDesc>,
+        metallic: Option<ParamDesc>,
+        roughness: Option<ParamDesc>,
+        specular: Option<ParamDesc>,
+        clearcoat: Option<ParamDesc>,
+        clearcoat_gloss: Option<ParamDesc>,
+        sheen: Option<ParamDesc>,
+        transmission: Option<ParamDesc>,
+        #[serde(default = "default_ior")]
+        ior: f64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ParamDesc {
+    Value(f64),
+    Color([f64; 3]),
+    Texture(String),
+}
+
+fn default_intensity() -> f64 { 1.0 }
+
+fn default_distance() -> f64 { 1.0 }
+
+fn default_ior() -> f64 { 1.5 }
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum IorDesc {
+    Crown,
+    Flint,
+    Diamond,
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl From<IorDesc> for Ior {
+    fn from(desc: IorDesc) -> Ior {
+        match desc {
+            IorDesc::Crown => Ior::crown(),
+            IorDesc::Flint => Ior::flint(),
+            IorDesc::Diamond => Ior::diamond(),
+            IorDesc::Cauchy { a, b } => Ior::Cauchy { a, b },
+            IorDesc::Sellmeier { b, c } => Ior::Sellmeier { b, c },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+#[derive(Debug)]
+struct ObjectDesc {
+    shape: ShapeDesc,
+    material: Option<Spanned<String>>,
+    /// shape filled by `constant_medium`
+    boundary: Option<Box<ObjectDesc>>,
+    /// also add the object to the importance sampling list
+    important: bool,
+    flip_normals: bool,
+    /// applied in order, after flipping normals
+    transform: Vec<TransformDesc>,
+    /// keyframes interpolated by ray time, applied after `transform`
+    animation: Vec<KeyframeDesc>,
+}
+
+/// Keys not common to all objects describe the shape. Not derived with `#[serde(flatten)]`,
+/// which neither rejects unknown keys nor keeps spans of the values.
+impl<'de> Deserialize<'de> for ObjectDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+struct ObjectVisitor;
+
+impl<'de> Visitor<'de> for ObjectVisitor {
+    type Value = ObjectDesc;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("object table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ObjectDesc, A::Error> {
+        let mut shape = toml::Table::new();
+        let (mut material, mut boundary) = (None, None);
+        let (mut important, mut flip_normals) = (false, false);
+        let (mut transform, mut animation) = (vec![], vec![]);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "material" => material = Some(map.next_value()?),
+                "boundary" => boundary = Some(map.next_value()?),
+                "important" => important = map.next_value()?,
+                "flip_normals" => flip_normals = map.next_value()?,
+                "transform" => transform = map.next_value()?,
+                "animation" => animation = map.next_value()?,
+                _ => { shape.insert(key, map.next_value()?); }
+            }
+        }
+        let shape = ShapeDesc::deserialize(toml::Value::Table(shape)).map_err(de::Error::custom)?;
+        Ok(ObjectDesc { shape, material, boundary, important, flip_normals, transform, animation })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere { center: [f64; 3], radius: f64 },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f64,
+    },
+    XyRect { x: [f64; 2], y: [f64; 2], k: f64 },
+    XzRect { x: [f64; 2], z: [f64; 2], k: f64 },
+    YzRect { y: [f64; 2], z: [f64; 2], k: f64 },
+    Box { min: [f64; 3], max: [f64; 3] },
+    /// Wavefront OBJ, `material` overrides the ones from its MTL files
+    Obj { path: String },
+    /// Stanford PLY, `material` overrides vertex colors
+    Ply { path: String },
+    ConstantMedium {
+        density: f64,
+        color: [f64; 3],
+    },
+}
+
+fn default_time1() -> f32 { 1.0 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate([f64; 3]),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate(RotationDesc),
+    Scale(ScaleDesc),
+    /// top three rows of an affine matrix
+    Matrix([[f64; 4]; 3]),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: [f64; 3],
+    angle: f64,
+}
+
+/// Object is scaled, then rotated around x, y, z and `rotate` axis in this order, then translated.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f32,
+    translate: Option<[f64; 3]>,
+    rotate_x: Option<f64>,
+    rotate_y: Option<f64>,
+    rotate_z: Option<f64>,
+    rotate: Option<RotationDesc>,
+    scale: Option<ScaleDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    PerAxis([f64; 3]),
+}
+
+/// Scene objects built from the description, camera is built once image size is known.
+pub struct World {
+    camera: CameraPath,
+    miss_shader: fn(&Ray) -> V3,
+    objects: Box<dyn Hittable>,
+    important: Box<dyn Hittable>,
+}
+
+impl World {
+    pub fn scene(self, r_type: RendererType, params: SceneParams) -> Scene {
+        let SceneParams { nx, ny, t_off, t_span, ttl } = params;
+        Scene {
+            camera: self.camera.camera((nx as f64) / (ny as f64), Shutter::new(t_off, t_span), ttl),
+            renderer: RendererImpl::pick_renderer(r_type, self.objects, self.important, self.miss_shader, ttl),
+        }
+    }
+
+    pub fn camera_path(&self) -> &CameraPath {
+        &self.camera
+    }
+}
+
+pub fn load(path: &Path, bvh: BvhBuilder) -> Result<World, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, dir, bvh)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Paths in the description are relative to `dir`.
+fn parse(source: &str, dir: &Path, bvh: BvhBuilder) -> Result<World, String> {
+    let file: SceneFile = toml::from_str(source).map_err(|err| err.to_string())?;
+    let mut loader = Loader {
+        source, dir, bvh,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: RefCell::new(HashMap::new()),
+    };
+
+    for (name, desc) in &file.textures {
+        let texture = loader.texture(name, desc)?;
+        loader.textures.insert(name.clone(), texture);
+    }
+    for (name, desc) in &file.materials {
+        let material = loader.material(desc)?;
+        loader.materials.insert(name.clone(), material);
+    }
+
+    let mut objs: Vec<Box<dyn Hittable>> = vec![];
+    let mut important: Vec<Box<dyn Hittable>> = vec![];
+    for desc in &file.objects {
+        let obj = loader.object(desc.get_ref(), desc.span())?;
+        if desc.get_ref().important {
+            // sampled towards and hit through the same geometry
+            let shared: Arc<dyn Hittable> = Arc::from(obj);
+            important.push(Box::new(Arc::clone(&shared)));
+            objs.push(Box::new(shared));
+        } else {
+            objs.push(obj);
+        }
+    }
+    if objs.is_empty() {
+        return Err("scene has no objects".to_string());
+    }
+    let important: Box<dyn Hittable> = if important.is_empty() {
+        Box::new(NoHit)
+    } else {
+        Box::new(HittableList::new(important))
+    };
+
+    let miss_shader: fn(&Ray) -> V3 = match file.miss {
+        MissShader::Sky => scenes::sky,
+        MissShader::Black => scenes::const_color_black,
+        MissShader::Dark => scenes::const_color_dark,
+        MissShader::Light => scenes::const_color_light,
+    };
+
+    Ok(World { camera: file.camera.path(&loader)?, miss_shader, objects: BVH::new(objs, bvh), important })
+}
+
+type MeshKey = (String, Option<String>);
+
+struct Loader<'a> {
+    source: &'a str,
+    dir: &'a Path,
+    bvh: BvhBuilder,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    /// meshes loaded so far by path and material override, objects using the same one share it
+    meshes: RefCell<HashMap<MeshKey, Arc<dyn Hittable>>>,
+}
+
+impl Loader<'_> {
+    /// Prefixes `msg` with the line `span` starts at.
+    fn error(&self, span: Range<usize>, msg: String) -> String {
+        let line = self.source[..span.start].matches('\n').count() + 1;
+        format!("line {}: {}", line, msg)
+    }
+
+    fn texture_ref(&self, name: &str, span: Range<usize>) -> Result<Arc<dyn Texture>, String> {
+        self.textures.get(name)
+            .cloned()
+            .ok_or_else(|| self.error(span, format!("unknown texture '{}'", name)))
+    }
+
+    fn material_ref(&self, name: &str, span: Range<usize>) -> Result<Arc<dyn Material>, String> {
+        self.materials.get(name)
+            .cloned()
+            .ok_or_else(|| self.error(span, format!("unknown material '{}'", name)))
+    }
+
+    /// Texture name takes precedence over plain color, white if neither is set.
+    fn color_or_texture(&self, color: &Option<[f64; 3]>, texture: &Option<String>, span: Range<usize>) -> Result<Box<dyn Texture>, String> {
+        match (texture, color) {
+            (Some(name), _) => Ok(Box::new(self.texture_ref(name, span)?)),
+            (None, Some(color)) => Ok(Box::new(Color(V3::from(*color)))),
+            (None, None) => Ok(Box::new(Color(V3::ones()))),
+        }
+    }
+
+    /// Perlin noise is seeded by `name`, so it's the same whatever order textures are loaded in.
+    fn texture(&self, name: &str, desc: &Spanned<TextureDesc>) -> Result<Arc<dyn Texture>, String> {
+        Ok(match desc.get_ref() {
+            TextureDesc::Color { color } => Arc::new(Color(V3::from(*color))),
+            TextureDesc::Checker { even, odd, step } =>
+                Arc::new(Checker::new(Color(V3::from(*even)), Color(V3::from(*odd)), *step)),
+            TextureDesc::Image { path } => {
+                let image = image::open(self.dir.join(path))
+                    .map_err(|err| self.error(desc.span(), format!("can't load '{}': {}", path, err)))?;
+                Arc::new(image.to_rgb())
+            }
+            TextureDesc::Perlin { scale, pattern } => {
+                let perlin = Perlin::new(&mut named_rng(name));
+                let noise: Box<dyn Fn(V3, f64) -> f64 + Sync + Send> = match pattern {
+                    PerlinPattern::Noise => Box::new(move |p, scale| perlin.noise(scale * p) * 0.5 + 0.5),
+                    PerlinPattern::Turbulence => Box::new(move |p, scale| perlin.turb(scale * p)),
+                    PerlinPattern::Marble => Box::new(move |p, scale| 0.5 * (1.0 + (scale * p.z + 10.0 * perlin.turb(p)).sin())),
+                };
+                Arc::new(PerlinTexture::new(noise, *scale))
+            }
+        })
+    }
+
+    fn material(&self, desc: &Spanned<MaterialDesc>) -> Result<Arc<dyn Material>, String> {
+        Ok(match desc.get_ref() {
+            MaterialDesc::Lambertian { color, texture } =>
+                Arc::new(Lambertian::texture(self.color_or_texture(color, texture, desc.span())?)),
+            MaterialDesc::Metal { albedo, fuzz } =>
+                Arc::new(Metal::new_fuzzed(V3::from(*albedo), *fuzz)),
+            MaterialDesc::Conductor { metal, eta, k, roughness } => match (metal, eta, k) {
+                (Some(MetalPreset::Gold), None, None) => Arc::new(Conductor::gold(*roughness)),
+                (Some(MetalPreset::Copper), None, None) => Arc::new(Conductor::copper(*roughness)),
+                (Some(MetalPreset::Aluminium), None, None) => Arc::new(Conductor::aluminium(*roughness)),
+                (None, Some(eta), Some(k)) => Arc::new(Conductor::new(V3::from(*eta), V3::from(*k), *roughness)),
+                _ => return Err(self.error(desc.span(), "conductor needs either `metal` or both `eta` and `k`".to_string())),
+            },
+            MaterialDesc::Dielectric { ref_idx, dispersion, color, roughness, absorption, color_at_distance, distance } => {
+                let albedo = color.map(V3::from).unwrap_or_else(V3::ones);
+                let ref_idx = match (ref_idx, dispersion) {
+                    (Some(ref_idx), None) => *ref_idx,
+                    (None, Some(ior)) if *roughness == 0.0 => Ior::from(*ior).ref_idx(),
+                    (None, Some(_)) => return Err(self.error(desc.span(), "dispersion needs smooth dielectric, without `roughness`".to_string())),
+                    _ => return Err(self.error(desc.span(), "dielectric needs either `ref_idx` or `dispersion`".to_string())),
+                };
+                let absorption = match (absorption, color_at_distance) {
+                    (None, None) => V3::zeros(),
+                    (Some(absorption), None) if absorption.iter().all(|&a| a >= 0.0) => V3::from(*absorption),
+                    (Some(_), None) => return Err(self.error(desc.span(), "`absorption` can't be negative".to_string())),
+                    (None, Some(_)) if *distance <= 0.0 => return Err(self.error(desc.span(), "`distance` must be positive".to_string())),
+                    (None, Some(color)) if color.iter().all(|&c| 0.0 < c && c <= 1.0) => absorption_for(V3::from(*color), *distance),
+                    (None, Some(_)) => return Err(self.error(desc.span(), "`color_at_distance` channels must be in (0, 1]".to_string())),
+                    _ => return Err(self.error(desc.span(), "dielectric takes either `absorption` or `color_at_distance`".to_string())),
+                };
+                if *roughness > 0.0 {
+                    Arc::new(RoughDielectric::new_colored(albedo, ref_idx, *roughness).with_absorption(absorption))
+                } else {
+                    let glass = Dielectric::new_colored(albedo, ref_idx).with_absorption(absorption);
+                    Arc::new(match dispersion {
+                        Some(ior) => glass.with_dispersion(Ior::from(*ior)),
+                        None => glass,
+                    })
+                }
+            }
+            MaterialDesc::DiffuseLight { color, texture, intensity } =>
+                Arc::new(DiffuseLight::new(self.color_or_texture(color, texture, desc.span())?, *intensity)),
+            MaterialDesc::Principled {
+                base_color, metallic, roughness, specular, clearcoat, clearcoat_gloss, sheen, transmission, ior
+            } => {
+                let span = desc.span();
+                let param = |desc: &Option<ParamDesc>, default: f64| self.param(desc, default, span.clone());
+                Arc::new(Principled::new(param(base_color, 1.0)?)
+                    .with_metallic(param(metallic, 0.0)?)
+                    .with_roughness(param(roughness, 0.5)?)
+                    .with_specular(param(specular, 0.5)?)
+                    .with_clearcoat(param(clearcoat, 0.0)?, param(clearcoat_gloss, 1.0)?)
+                    .with_sheen(param(sheen, 0.0)?)
+                    .with_transmission(param(transmission, 0.0)?, *ior))
+            }
+        })
+    }
+
+    /// Number as gray, color or texture name, `default` gray if unset.
+    fn param(&self, desc: &Option<ParamDesc>, default: f64, span: Range<usize>) -> Result<Box<dyn Texture>, String> {
+        Ok(match desc {
+            None => Box::new(Color(V3::all(default))),
+            Some(ParamDesc::Value(value)) => Box::new(Color(V3::all(*value))),
+            Some(ParamDesc::Color(color)) => Box::new(Color(V3::from(*color))),
+            Some(ParamDesc::Texture(name)) => Box::new(self.texture_ref(name, span)?),
+        })
+    }
+
+    /// Errors point at the top-level object `obj` belongs to, `span` is its location.
+    fn object(&self, obj: &ObjectDesc, span: Range<usize>) -> Result<Box<dyn Hittable>, String> {
+        let material = || match &obj.material {
+            Some(name) => self.material_ref(name.get_ref(), name.span()),
+            None => Err(self.error(span.clone(), "object has no material".to_string())),
+        };
+        if obj.boundary.is_some() && !matches!(obj.shape, ShapeDesc::ConstantMedium { .. }) {
+            return Err(self.error(span, "only constant_medium has `boundary`".to_string()));
+        }
+        let range = |[start, end]: [f64; 2]| start..end;
+
+        let mut hittable: Box<dyn Hittable> = match &obj.shape {
+            ShapeDesc::Sphere { center, radius } =>
+                Box::new(Sphere::new(V3::from(*center), *radius, material()?)),
+            ShapeDesc::MovingSphere { center0, center1, time0, time1, radius } =>
+                Box::new(MovingSphere::new(V3::from(*center0), V3::from(*center1),
+                                           *time0, *time1, *radius, Box::new(material()?))),
+            ShapeDesc::XyRect { x, y, k } => Box::new(XYRect::new(range(*x), range(*y), *k, material()?)),
+            ShapeDesc::XzRect { x, z, k } => Box::new(XZRect::new(range(*x), range(*z), *k, material()?)),
+            ShapeDesc::YzRect { y, z, k } => Box::new(YZRect::new(range(*y), range(*z), *k, material()?)),
+            ShapeDesc::Box { min, max } => Box::new(AABox::mono(
+                min[0]..max[0], min[1]..max[1], min[2]..max[2], material()?)),
+            ShapeDesc::Obj { path } | ShapeDesc::Ply { path } =>
+                Box::new(self.mesh(path, &obj.shape, &obj.material, span.clone())?),
+            ShapeDesc::ConstantMedium { density, color } => {
+                let boundary = obj.boundary.as_ref()
+                    .ok_or_else(|| self.error(span.clone(), "constant_medium needs `boundary`".to_string()))?;
+                Box::new(ConstantMedium::new(self.object(boundary, span.clone())?, *density, Color(V3::from(*color))))
+            }
+        };
+        if obj.flip_normals {
+            hittable = Box::new(hittable.flip_normals());
+        }
+        if !obj.transform.is_empty() {
+            hittable = Box::new(self.transform(hittable, &obj.transform, span.clone())?);
+        }
+        if !obj.animation.is_empty() {
+            let mut animated = hittable.animate();
+            for desc in &obj.animation {
+                let mut keyframe = Keyframe::new(desc.time);
+                if let Some(scale) = &desc.scale {
+                    keyframe = keyframe.scale(self.scale(scale, span.clone())?);
+                }
+                let rotations = [
+                    (V3::new(1.0, 0.0, 0.0), desc.rotate_x),
+                    (V3::new(0.0, 1.0, 0.0), desc.rotate_y),
+                    (V3::new(0.0, 0.0, 1.0), desc.rotate_z),
+                ];
+                for (axis, angle) in rotations.iter() {
+                    if let Some(angle) = angle {
+                        keyframe = keyframe.rotate(*axis, *angle);
+                    }
+                }
+                if let Some(rotation) = &desc.rotate {
+                    let (axis, angle) = self.rotation(rotation, span.clone())?;
+                    keyframe = keyframe.rotate(axis, angle);
+                }
+                if let Some(offset) = desc.translate {
+                    keyframe = keyframe.translate(V3::from(offset));
+                }
+                animated = animated.keyframe(keyframe);
+            }
+            hittable = Box::new(animated);
+        }
+        Ok(hittable)
+    }
+
+    fn transform(&self, hittable: Box<dyn Hittable>, transforms: &[TransformDesc], span: Range<usize>) -> Result<Transform<Box<dyn Hittable>>, String> {
+        let mut transformed = hittable.transform();
+        for transform in transforms {
+            transformed = match transform {
+                TransformDesc::Translate(offset) => transformed.translate(V3::from(*offset)),
+                TransformDesc::RotateX(angle) => transformed.rotate_x(*angle),
+                TransformDesc::RotateY(angle) => transformed.rotate_y(*angle),
+                TransformDesc::RotateZ(angle) => transformed.rotate_z(*angle),
+                TransformDesc::Rotate(rotation) => {
+                    let (axis, angle) = self.rotation(rotation, span.clone())?;
+                    transformed.rotate(axis, angle)
+                }
+                TransformDesc::Scale(scale) => transformed.scale(self.scale(scale, span.clone())?),
+                TransformDesc::Matrix([r0, r1, r2]) => transformed
+                    .then(M4([*r0, *r1, *r2, [0.0, 0.0, 0.0, 1.0]]))
+                    .ok_or_else(|| self.error(span.clone(), "transform matrix is singular".to_string()))?,
+            };
+        }
+        Ok(transformed)
+    }
+
+    fn rotation(&self, desc: &RotationDesc, span: Range<usize>) -> Result<(V3, f64), String> {
+        let axis = V3::from(desc.axis);
+        if axis.sqr_length() == 0.0 {
+            return Err(self.error(span, "rotation axis can't be zero".to_string()));
+        }
+        Ok((axis, desc.angle))
+    }
+
+    fn scale(&self, desc: &ScaleDesc, span: Range<usize>) -> Result<V3, String> {
+        let factors = match desc {
+            ScaleDesc::Uniform(factor) => V3::all(*factor),
+            ScaleDesc::PerAxis(factors) => V3::from(*factors),
+        };
+        if factors.x * factors.y * factors.z == 0.0 {
+            return Err(self.error(span, "scale factors can't be zero".to_string()));
+        }
+        Ok(factors)
+    }
+
+    fn mesh(&self, path: &str, shape: &ShapeDesc, material: &Option<Spanned<String>>, span: Range<usize>) -> Result<Arc<dyn Hittable>, String> {
+        let key = (path.to_string(), material.as_ref().map(|name| name.get_ref().clone()));
+        if let Some(mesh) = self.meshes.borrow().get(&key) {
+            return Ok(Arc::clone(mesh));
+        }
+        let material = match material {
+            Some(name) => Some(self.material_ref(name.get_ref(), name.span())?),
+            None => None,
+        };
+        let load = if let ShapeDesc::Obj { .. } = shape { load_obj } else { load_ply };
+        let mesh: Arc<dyn Hittable> = load(&self.dir.join(path), material, self.bvh)
+            .map_err(|err| self.error(span, err))?
+            .into();
+        self.meshes.borrow_mut().insert(key, Arc::clone(&mesh));
+        Ok(mesh)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use toml::Spanned;
+
+    use crate::bvh::BvhBuilder;
+    use crate::random::with_rnd;
+    use crate::scene_file::{parse, Loader, PerlinPattern, TextureDesc};
+    use crate::vec::V3;
+
+    #[test]
+    fn test_example_scene_loads() {
+        let sources = [
+            include_str!("../scenes/cornel_box.toml"),
+            include_str!("../scenes/cornel_motion.toml"),
+            include_str!("../scenes/turntable.toml"),
+            include_str!("../scenes/bokeh.toml"),
+            include_str!("../scenes/metals.toml"),
+            include_str!("../scenes/cornel_frosted.toml"),
+            include_str!("../scenes/glass_slabs.toml"),
+            include_str!("../scenes/prism.toml"),
+            include_str!("../scenes/principled.toml"),
+        ];
+        for source in sources.iter() {
+            let result = parse(source, Path::new("scenes"), BvhBuilder::default());
+            assert!(result.is_ok(), "{}", result.err().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_unknown_material_points_at_line() {
+        let source = r#"
+[camera]
+from = [0, 0, -1]
+at = [0, 0, 0]
+vfov = 40
+
+[materials.white]
+type = "lambertian"
+color = [0.7, 0.7, 0.7]
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "whte"
+"#;
+        let err = parse(source, Path::new("scenes"), BvhBuilder::default()).err().unwrap();
+        assert!(err.starts_with("line 15:"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_object_key_is_rejected() {
+        let source = r#"
+[camera]
+from = [0, 0, -1]
+at = [0, 0, 0]
+vfov = 40
+
+[materials.white]
+type = "lambertian"
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+flip_normal = true
+"#;
+        let err = parse(source, Path::new("scenes"), BvhBuilder::default()).err().unwrap();
+        assert!(err.contains("unknown field `flip_normal`"), "{}", err);
+    }
+
+    #[test]
+    fn test_perlin_texture_depends_on_name_only() {
+        let loader = Loader {
+            source: "", dir: Path::new(""), bvh: BvhBuilder::default(),
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            meshes: RefCell::new(HashMap::new()),
+        };
+        let desc = Spanned::new(0..0, TextureDesc::Perlin { scale: 4.0, pattern: PerlinPattern::Noise });
+        let point = V3::new(0.3, 0.7, 0.1);
+        let marble = loader.texture("marble", &desc).unwrap().value(0.0, 0.0, point).0;
+        with_rnd(|rnd| rnd.next_u64());
+        assert_eq!(loader.texture("marble", &desc).unwrap().value(0.0, 0.0, point).0, marble);
+        assert_ne!(loader.texture("wood", &desc).unwrap().value(0.0, 0.0, point).0, marble);
+    }
+
+    #[test]
+    fn test_invalid_absorption_is_reported() {
+        let scene = |glass: &str| format!(r#"
+[camera]
+from = [0, 0, -1]
+at = [0, 0, 0]
+vfov = 40
+
+[materials.glass]
+type = "dielectric"
+ref_idx = 1.5
+{}
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "glass"
+"#, glass);
+        let error = |glass: &str| parse(&scene(glass), Path::new("scenes"), BvhBuilder::default()).err();
+        assert!(error("color_at_distance = [0.5, 0.5, 0.5]\ndistance = 2").is_none());
+        assert_eq!(error("color_at_distance = [0.5, 0.5, 0.5]\ndistance = 0").unwrap(), "line 7: `distance` must be positive");
+        assert!(error("color_at_distance = [0.5, 0, 0.5]").unwrap().contains("must be in (0, 1]"));
+        assert!(error("color_at_distance = [0.5, 1.2, 0.5]").unwrap().contains("must be in (0, 1]"));
+        assert!(error("absorption = [0.5, -1, 0.5]").unwrap().contains("can't be negative"));
+    }
+
+    #[test]
+    fn test_non_finite_keyframe_time_is_reported() {
+        let source = std::fs::read_to_string("scenes/turntable.toml").unwrap()
+            .replacen("time = 0.5", "time = nan", 1);
+        let result = parse(&source, Path::new("scenes"), BvhBuilder::default());
+        assert_eq!(result.err().unwrap(), "line 17: keyframe `time` must be finite");
+    }
+
+    #[test]
+    fn test_singular_transform_is_reported() {
+        let source = r#"
+[camera]
+from = [0, 0, -1]
+at = [0, 0, 0]
+vfov = 40
+
+[materials.white]
+type = "lambertian"
+color = [0.7, 0.7, 0.7]
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+transform = [
+    { rotate = { axis = [1, 1, 0], angle = 30 } },
+    { matrix = [[1, 0, 0, 0], [0, 1, 0, 0], [1, 1, 0, 5]] },
+]
+"#;
+        let err = parse(source, Path::new("scenes"), BvhBuilder::default()).err().unwrap();
+        assert_eq!(err, "line 11: transform matrix is singular");
+    }
+}
@@ -1,28 +1,44 @@
+use std::f64::consts::PI;
 use std::sync::Arc;
 
-use crate::hittable::{AABox, ConstantMedium, Hittable, HittableList, RotateYOp, FlipNormalsOp, TranslateOp, MovingSphere, Sphere, XYRect, XZRect, YZRect, NoHit, AABoxMono, Translate};
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::hittable::{AABox, ConstantMedium, Hittable, HittableList, RotateYOp, FlipNormalsOp, TranslateOp, MovingSphere, Sphere, XYRect, XZRect, YZRect, NoHit, AABoxMono, Translate, Triangle};
+use crate::material::{Dielectric, DiffuseLight, Ior, Lambertian, Material, Metal};
 use crate::noise::Perlin;
 use crate::random::{next_color, next_std_f64, with_rnd, next_std_u32};
 use crate::texture::{Checker, Color, ImageTexture, PerlinTexture};
 use crate::vec::V3;
-use crate::camera::Camera;
+use crate::camera::{Camera, Perspective, Shutter};
 use crate::renderer::{Renderer, RendererImpl, RendererType};
 use crate::ray::Ray;
-use crate::bvh::BVH;
+use crate::bvh::{BVH, BvhBuilder};
+use crate::mesh::build_mesh;
+
+/// Image size, shutter interval and bounce limit the built-in scenes are set up for.
+#[derive(Debug, Copy, Clone)]
+pub struct SceneParams {
+    pub nx: u32,
+    pub ny: u32,
+    pub t_off: f32,
+    pub t_span: f32,
+    pub ttl: i32,
+}
 
 pub struct Scene {
-    pub camera: Camera,
+    pub camera: Box<dyn Camera>,
     pub renderer: RendererImpl,
 }
 
 impl Scene {
     pub fn color(&self, u: f64, v: f64) -> V3 {
-        self.renderer.color(&self.camera.get_ray(u, v))
+        match self.camera.get_ray(u, v) {
+            Some(ray) => self.camera.exposure() * self.renderer.color(&ray),
+            None => V3::zeros(),
+        }
     }
 }
 
-pub fn perlin_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn perlin_scene(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let perlin = with_rnd(|rnd| Perlin::new(rnd));
     let mut objs: Vec<Box<dyn Hittable>> = vec![];
     objs.push(Box::new(
@@ -58,7 +74,8 @@ pub fn perlin_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span:
     }
 }
 
-pub fn img_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn img_scene(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let mut objs: Vec<Box<dyn Hittable>> = Vec::new();
     objs.push(Box::new(
         Sphere::new(V3::new(0.0, -1000.0, 0.0), 1000.0,
@@ -83,7 +100,8 @@ pub fn img_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32
     }
 }
 
-pub fn img_lit_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn img_lit_scene(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let light = Sphere::new(V3::new(0.0, 3.0, -2.0), 2.0,
         DiffuseLight::new(Box::new(Color::new(1.0, 1.0, 0.99)), 2.0));
     let light1 = Sphere::new(V3::new(0.0, 3.0, -2.0), 2.0,
@@ -108,7 +126,8 @@ pub fn img_lit_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span:
 }
 
 #[allow(dead_code)]
-pub fn img_lit_rect_scene(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn img_lit_rect_scene(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let l1 = Box::new(XZRect::new(-1.0..1.0, -1.0..1.0, 2.5, Arc::new(
         DiffuseLight::new(Box::new(Color::new(1.0, 1.0, 0.99)), 4.0))));
     let l2 = Box::new(XYRect::new(-1.0..1.0, 0.5..1.5, -1.5, Arc::new(
@@ -151,7 +170,8 @@ fn cornel_box_prototype() -> Vec<Box<dyn Hittable>> {
     ]
 }
 
-pub fn cornel_box_with_instances(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn cornel_box_with_instances(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let mut objs = cornel_box_prototype();
     objs.push(
         Box::new(AABox::mono(0.0..165.0, 0.0..165.0, 0.0..165.0,
@@ -160,17 +180,17 @@ pub fn cornel_box_with_instances(r_type: RendererType, nx: u32, ny: u32, t_off:
             .translate(V3::new(130.0, 0.0, 65.0))
         ));
 
-    let shiny_box = Box::new(AABox::mono(0.0..165.0, 0.0..330.0, 0.0..165.0,
+    let shiny_box = Arc::new(AABox::mono(0.0..165.0, 0.0..330.0, 0.0..165.0,
                                  // Arc::new(Lambertian::new(Color(V3::all(0.73)))))
                                  Arc::new(Metal::new(V3::all(1.0))))
         .rotate_y(15.0)
         .translate(V3::new(265.0, 0.0, 295.0))
     );
-    objs.push(shiny_box.clone());
+    objs.push(Box::new(Arc::clone(&shiny_box)));
 
     let light_mat = Arc::new(DiffuseLight::new(Box::new(Color::new(1.0, 1.0, 1.0)), 15.0));
-    let light = XZRect::new(213.0..343.0, 227.0..332.0, 554.0, light_mat);
-    objs.push(Box::new(light.clone().flip_normals()));
+    let light = Arc::new(XZRect::new(213.0..343.0, 227.0..332.0, 554.0, light_mat));
+    objs.push(Box::new(Arc::clone(&light).flip_normals()));
     objs.swap_remove(2);
 
     Scene {
@@ -178,14 +198,15 @@ pub fn cornel_box_with_instances(r_type: RendererType, nx: u32, ny: u32, t_off:
         renderer: RendererImpl::pick_renderer(
             r_type,
             Box::new(HittableList::new(objs)),
-            Box::new(HittableList::new(vec![shiny_box, Box::new(light)])),
+            Box::new(HittableList::new(vec![Box::new(shiny_box), Box::new(light)])),
             self::const_color_black,
             ttl
         ),
     }
 }
 
-pub fn cornel_box_with_is(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn cornel_box_with_is(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let mut objs = cornel_box_prototype();
     let mut important: Vec<Box<dyn Hittable>> = vec![];
     /*objs.push(
@@ -208,7 +229,7 @@ pub fn cornel_box_with_is(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_
 
     let lamb = Arc::new(Lambertian::new(Color(V3::all(0.73))));
     let metal = Arc::new(Metal::new(V3::all(1.0)));
-    let shiny_box =
+    let shiny_box = Arc::new(
         AABox::new(0.0..165.0, 0.0..330.0, 0.0..165.0,
                        // AABox::new(265.0..(165.0+265.0), 0.0..330.0, 295.0..(165.0+265.0),
                        lamb.clone(),
@@ -223,31 +244,31 @@ pub fn cornel_box_with_is(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_
         // .rotate_y(-90.0)
         // .translate(V3::new(265.0, 80.0, 295.0))
         .translate(V3::new(265.0, 0.0, 295.0))
-        ;
+    );
 
     let light_mat = Arc::new(DiffuseLight::new(Box::new(Color::new(1.0, 1.0, 1.0)), 15.0));
-    let light = Box::new(XZRect::new(213.0..343.0, 227.0..332.0, 554.0, light_mat).flip_normals());
-    objs.push(light.clone());
+    let light = Arc::new(XZRect::new(213.0..343.0, 227.0..332.0, 554.0, light_mat).flip_normals());
+    objs.push(Box::new(Arc::clone(&light)));
     objs.swap_remove(2);
 
     let sphere: Box<Translate<Sphere<Dielectric>>> = Box::new(
         Sphere::new(
             V3::new(-87.5, 87.5, -12.5),
             88.5,
-            Dielectric::new(1.5),
+            Dielectric::new(1.5).with_dispersion(Ior::flint()),
         )
             .translate(V3::new(130.0, 0.0, 65.0))
             .translate(V3::new(165.0, 165.0, 165.0))
     );
 
     objs.push(fog.clone());
-    objs.push(Box::new(shiny_box.clone()));
+    objs.push(Box::new(Arc::clone(&shiny_box)));
     objs.push(sphere.clone());
 
     important.push(fog);
-    important.push(Box::new(shiny_box.clone()));
+    important.push(Box::new(shiny_box));
     important.push(sphere);
-    important.push(light);
+    important.push(Box::new(light));
     let important = Box::new(HittableList::new(important));
 
     Scene {
@@ -262,7 +283,8 @@ pub fn cornel_box_with_is(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_
     }
 }
 
-pub fn cornel_box_volumes(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn cornel_box_volumes(r_type: RendererType, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let mut objs = cornel_box_prototype();
     objs.push(Box::new(ConstantMedium::new(
         AABox::mono(0.0..165.0, 0.0..165.0, 0.0..165.0,
@@ -301,7 +323,8 @@ pub fn cornel_box_volumes(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_
     }
 }
 
-pub fn weekend_final(r_type: RendererType, complexity: i8, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn weekend_final(r_type: RendererType, bvh: BvhBuilder, complexity: i8, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let mut objs: Vec<Box<dyn Hittable>> = vec![
         Box::new(Sphere::new(V3::new(0.0, -1000.0, 0.0), 1000.0,
             Lambertian::texture(Box::new(Checker::new(
@@ -343,7 +366,7 @@ pub fn weekend_final(r_type: RendererType, complexity: i8, nx: u32, ny: u32, t_o
         camera: get_cam(nx, ny, t_off, t_span, ttl),
         renderer: RendererImpl::pick_renderer(
             r_type,
-            BVH::new(objs),
+            BVH::new(objs, bvh),
             Box::new(NoHit),
             self::sky,
             ttl
@@ -351,7 +374,8 @@ pub fn weekend_final(r_type: RendererType, complexity: i8, nx: u32, ny: u32, t_o
     }
 }
 
-pub fn next_week(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Scene {
+pub fn next_week(r_type: RendererType, bvh: BvhBuilder, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
     let nb = 20;
     let ground = Arc::new(Lambertian::new(Color(V3::new(0.48, 0.83, 0.53))));
     let mut objs: Vec<Box<dyn Hittable>> = vec![];
@@ -368,7 +392,7 @@ pub fn next_week(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32
             boxes.push(Box::new(AABox::mono(x0..x1, y0..y1, z0..z1, ground.clone())));
         }
     }
-    objs.push(BVH::new(boxes));
+    objs.push(BVH::new(boxes, bvh));
 
     let light_mat = Arc::new(DiffuseLight::new(Box::new(Color(V3::ones())), 7.0));
     let light = XZRect::new(123.0..423.0, 147.0..412.0, 554.0, light_mat);
@@ -423,7 +447,7 @@ pub fn next_week(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32
         )));
     }
 /*    objs.push(
-        BVH::new(foam_box)
+        BVH::new(foam_box, bvh)
             .rotate_y(15.0)
             .translate(V3::new(-100.0, 270.0, 395.0))
     );*/
@@ -431,7 +455,7 @@ pub fn next_week(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32
         camera: next_week_cam(nx, ny, t_off, t_span, ttl),
         renderer: RendererImpl::pick_renderer(
             r_type,
-            BVH::new(objs),
+            BVH::new(objs, bvh),
             Box::new(light.flip_normals()),
             |_| V3::zeros(),
             ttl
@@ -439,7 +463,77 @@ pub fn next_week(r_type: RendererType, nx: u32, ny: u32, t_off: f32, t_span: f32
     }
 }
 
-fn cornel_box_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera {
+/// Ten thousand instances of one tree mesh, each placed with its own rotation and offset.
+pub fn forest(r_type: RendererType, bvh: BvhBuilder, params: SceneParams) -> Scene {
+    let SceneParams { nx, ny, t_off, t_span, ttl } = params;
+    let tree: Arc<dyn Hittable> = Arc::from(tree_mesh(bvh));
+    let mut trees: Vec<Box<dyn Hittable>> = vec![];
+    for i in -50..50 {
+        for j in -50..50 {
+            let position = V3::new(
+                4.0 * (i as f64 + next_std_f64()),
+                0.0,
+                4.0 * (j as f64 + next_std_f64()),
+            );
+            trees.push(Box::new(Arc::clone(&tree)
+                .rotate_y(360.0 * next_std_f64())
+                .translate(position)));
+        }
+    }
+    let objs: Vec<Box<dyn Hittable>> = vec![
+        Box::new(Sphere::new(V3::new(0.0, -10000.0, 0.0), 10000.0,
+                             Lambertian::new(Color::new(0.35, 0.3, 0.2)))),
+        BVH::new(trees, bvh),
+    ];
+
+    let aspect = (nx as f64) / (ny as f64);
+    let from = V3::new(-30.0, 14.0, -225.0);
+    let at = V3::new(20.0, 2.0, -120.0);
+    Scene {
+        camera: Box::new(Perspective::new_look(from, at, V3::new(0.0, 1.0, 0.0), 40.0, aspect,
+                                               (from - at).length(), 0.0, Shutter::new(t_off, t_span), ttl)),
+        renderer: RendererImpl::pick_renderer(
+            r_type,
+            Box::new(HittableList::new(objs)),
+            Box::new(NoHit),
+            self::sky,
+            ttl,
+        ),
+    }
+}
+
+/// Fir of three stacked cones on a hexagonal trunk, base at the origin.
+fn tree_mesh(bvh: BvhBuilder) -> Box<dyn Hittable> {
+    let bark: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.3, 0.2, 0.1)));
+    let needles: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.1, 0.35, 0.12)));
+    let ring = |segments: usize, radius: f64, y: f64| -> Vec<V3> {
+        (0..=segments)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f64 / segments as f64;
+                V3::new(radius * angle.cos(), y, radius * angle.sin())
+            })
+            .collect()
+    };
+
+    let mut triangles = vec![];
+    let (bottom, top) = (ring(6, 0.3, 0.0), ring(6, 0.3, 1.5));
+    for i in 0..6 {
+        triangles.push(Triangle::new([bottom[i], top[i], top[i + 1]], Arc::clone(&bark)));
+        triangles.push(Triangle::new([bottom[i], top[i + 1], bottom[i + 1]], Arc::clone(&bark)));
+    }
+    for &(radius, y, height) in &[(2.0, 1.0, 2.5), (1.6, 2.4, 2.2), (1.1, 3.6, 2.0)] {
+        let base = ring(12, radius, y);
+        let apex = V3::new(0.0, y + height, 0.0);
+        let center = V3::new(0.0, y, 0.0);
+        for i in 0..12 {
+            triangles.push(Triangle::new([base[i], apex, base[i + 1]], Arc::clone(&needles)));
+            triangles.push(Triangle::new([center, base[i], base[i + 1]], Arc::clone(&needles)));
+        }
+    }
+    build_mesh(triangles, bvh).unwrap()
+}
+
+fn cornel_box_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Box<dyn Camera> {
     let aspect = (nx as f64) / (ny as f64);
     let from = V3::new(278.0, 278.0, -680.0);
     let at = V3::new(278.0, 278.0, 0.0);
@@ -447,19 +541,19 @@ fn cornel_box_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera
     let dist_to_focus = 2.0;
     let aperture = 0.00;
     let vfov = 80.0;
-    Camera::new_look(
+    Box::new(Perspective::new_look(
         from, at,
         /*    up*/ V3::new(0.0, 1.0, 0.0),
         vfov,
         aspect,
         dist_to_focus,
         aperture,
-        t_off, t_span,
+        Shutter::new(t_off, t_span),
         ttl,
-    )
+    ))
 }
 
-fn get_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera {
+fn get_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Box<dyn Camera> {
     let aspect = (nx as f64) / (ny as f64);
     let from = V3::new(13.0, 2.0, 3.0);
     let at = V3::new(0.0, 0.0, 0.0);
@@ -467,38 +561,38 @@ fn get_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera {
     let dist_to_focus = 10.0;
     let aperture = 0.0;
     let vfov = 40.0;
-    Camera::new_look(
+    Box::new(Perspective::new_look(
         from, at,
         /*    up*/ V3::new(0.0, 1.0, 0.0),
         vfov,
         aspect,
         dist_to_focus,
         aperture,
-        t_off, t_span,
+        Shutter::new(t_off, t_span),
         ttl,
-    )
+    ))
 }
 
-fn closeup_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera {
+fn closeup_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Box<dyn Camera> {
     let aspect = (nx as f64) / (ny as f64);
     let from = V3::new(-3.0, 3.0, 2.0);
     let at = V3::new(0.0, 0.0, -1.0);
     let dist_to_focus = (from - at).length();
     let aperture = 0.01;
-    Camera::new_look(
+    Box::new(Perspective::new_look(
         from, at,
         /*    up*/ V3::new(0.0, 1.0, 0.0),
         /*  vfov*/ 80.0,
         aspect,
         dist_to_focus,
         aperture,
-        t_off, t_span,
+        Shutter::new(t_off, t_span),
         ttl,
-    )
+    ))
 }
 
 
-fn next_week_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera {
+fn next_week_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Box<dyn Camera> {
     let aspect = (nx as f64) / (ny as f64);
     let from = V3::new(478.0, 278.0, -680.0);
     // let at = V3::new(278.0, 170.0, 40.0);
@@ -509,25 +603,25 @@ fn next_week_cam(nx: u32, ny: u32, t_off: f32, t_span: f32, ttl: i32) -> Camera
     // let vfov = 22.0;
     let vfov = 62.0;
 
-    Camera::new_look(
+    Box::new(Perspective::new_look(
         from, at,
         /*    up*/ V3::new(0.0, 1.0, 0.0),
         vfov,
         aspect,
         dist_to_focus,
         aperture,
-        t_off, t_span,
+        Shutter::new(t_off, t_span),
         ttl,
-    )
+    ))
 }
 
 
-fn sky(r: &Ray) -> V3 {
+pub fn sky(r: &Ray) -> V3 {
     let t: f64 = 0.5 * (r.direction.y / r.direction.length() + 1.0);
     return (1.0 - t) * V3::ones() + t * V3::new(0.5, 0.7, 1.0);
 }
 
-fn const_color_dark(_: &Ray) -> V3 { V3::new(0.05088, 0.05088, 0.05088) }
-fn const_color_black(_: &Ray) -> V3 { V3::new(0., 0., 0.) }
+pub fn const_color_dark(_: &Ray) -> V3 { V3::new(0.05088, 0.05088, 0.05088) }
+pub fn const_color_black(_: &Ray) -> V3 { V3::new(0., 0., 0.) }
 
-fn const_color_light(_: &Ray) -> V3 { V3::new(0.3, 0.3, 0.3) }
+pub fn const_color_light(_: &Ray) -> V3 { V3::new(0.3, 0.3, 0.3) }
@@ -0,0 +1,278 @@
+use std::str::FromStr;
+
+use crate::random::mix;
+
+/// Source of sample values: `dimension`-th 2D point of `index`-th sample of `pixel`.
+/// Returns `None` when the sequence has nothing to offer for the request,
+/// then the caller falls back to independent random numbers.
+pub trait Sequence {
+    fn sample_2d(&self, seed: u64, pixel: u64, index: u64, dimension: u32) -> Option<[f64; 2]>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SequenceType {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SequenceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Result::Ok(SequenceType::Independent),
+            "stratified" => Result::Ok(SequenceType::Stratified),
+            "halton" => Result::Ok(SequenceType::Halton),
+            "sobol" => Result::Ok(SequenceType::Sobol),
+            other => Result::Err(format!("Unknown variant: '{}'", other))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SequenceImpl {
+    Independent(Independent),
+    Stratified(Stratified),
+    Halton(Halton),
+    Sobol(Sobol),
+}
+
+impl SequenceImpl {
+    pub fn pick(sequence_type: SequenceType, samples: usize) -> SequenceImpl {
+        match sequence_type {
+            SequenceType::Independent => SequenceImpl::Independent(Independent),
+            SequenceType::Stratified => SequenceImpl::Stratified(Stratified::new(samples)),
+            SequenceType::Halton => SequenceImpl::Halton(Halton),
+            SequenceType::Sobol => SequenceImpl::Sobol(Sobol),
+        }
+    }
+}
+
+impl SequenceImpl {
+    pub fn sequence_type(&self) -> SequenceType {
+        match self {
+            SequenceImpl::Independent(_) => SequenceType::Independent,
+            SequenceImpl::Stratified(_) => SequenceType::Stratified,
+            SequenceImpl::Halton(_) => SequenceType::Halton,
+            SequenceImpl::Sobol(_) => SequenceType::Sobol,
+        }
+    }
+}
+
+impl Sequence for SequenceImpl {
+    fn sample_2d(&self, seed: u64, pixel: u64, index: u64, dimension: u32) -> Option<[f64; 2]> {
+        match self {
+            SequenceImpl::Independent(sequence) => sequence.sample_2d(seed, pixel, index, dimension),
+            SequenceImpl::Stratified(sequence) => sequence.sample_2d(seed, pixel, index, dimension),
+            SequenceImpl::Halton(sequence) => sequence.sample_2d(seed, pixel, index, dimension),
+            SequenceImpl::Sobol(sequence) => sequence.sample_2d(seed, pixel, index, dimension),
+        }
+    }
+}
+
+/// Plain pseudo-random numbers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Independent;
+
+impl Sequence for Independent {
+    fn sample_2d(&self, _: u64, _: u64, _: u64, _: u32) -> Option<[f64; 2]> {
+        None
+    }
+}
+
+/// Jittered `strata x strata` grid, sample indices are shuffled per pixel and dimension,
+/// so that dimensions don't correlate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stratified {
+    strata: u32,
+}
+
+impl Stratified {
+    pub fn new(samples: usize) -> Stratified {
+        Stratified { strata: f64::ceil(f64::sqrt(samples as f64)) as u32 }
+    }
+}
+
+impl Sequence for Stratified {
+    fn sample_2d(&self, seed: u64, pixel: u64, index: u64, dimension: u32) -> Option<[f64; 2]> {
+        let cells = self.strata * self.strata;
+        if index >= cells as u64 { return None; }
+        let key = mix(mix(seed ^ pixel) ^ dimension as u64);
+        let cell = permute(index as u32, cells, key as u32);
+        let jitter = mix(key ^ index);
+        let n = self.strata as f64;
+        Some([
+            ((cell % self.strata) as f64 + to_unit(jitter as u32)) / n,
+            ((cell / self.strata) as f64 + to_unit((jitter >> 32) as u32)) / n,
+        ])
+    }
+}
+
+/// Halton sequence with Owen-scrambled digits, keyed per pixel and dimension,
+/// without scrambling higher prime bases correlate badly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Halton;
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+impl Sequence for Halton {
+    fn sample_2d(&self, seed: u64, pixel: u64, index: u64, dimension: u32) -> Option<[f64; 2]> {
+        let base = 2 * dimension as usize;
+        if base + 1 >= PRIMES.len() { return None; }
+        let key = mix(mix(seed ^ pixel) ^ dimension as u64);
+        Some([
+            scrambled_radical_inverse(index, PRIMES[base], key),
+            scrambled_radical_inverse(index, PRIMES[base + 1], mix(key)),
+        ])
+    }
+}
+
+/// Radical inverse with every digit permuted depending on the digits before it.
+fn scrambled_radical_inverse(mut index: u64, base: u64, key: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut prefix = key;
+    let mut result = 0.0;
+    // enough digits for 32 bits of precision
+    while inv_base_n > 1.0 / (1u64 << 32) as f64 {
+        let digit = index % base;
+        let permuted = permute(digit as u32, base as u32, prefix as u32);
+        inv_base_n *= inv_base;
+        result += permuted as f64 * inv_base_n;
+        prefix = mix(prefix ^ digit);
+        index /= base;
+    }
+    f64::min(result, 1.0 - f64::EPSILON)
+}
+
+/// Owen-scrambled 2D Sobol points, padded across dimensions:
+/// every dimension pair shuffles the sample order independently,
+/// see Burley, "Practical Hash-based Owen Scrambling", 2020.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sobol;
+
+impl Sequence for Sobol {
+    fn sample_2d(&self, seed: u64, pixel: u64, index: u64, dimension: u32) -> Option<[f64; 2]> {
+        if index > u32::MAX as u64 { return None; }
+        let key = mix(mix(seed ^ pixel) ^ dimension as u64);
+        let shuffled = nested_uniform_scramble(index as u32, key as u32);
+        let (x, y) = sobol_2d(shuffled);
+        Some([
+            to_unit(nested_uniform_scramble(x, (key >> 32) as u32)),
+            to_unit(nested_uniform_scramble(y, mix(key) as u32)),
+        ])
+    }
+}
+
+/// First two Sobol dimensions: van der Corput and the one built from `x + 1` polynomial.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut direction = 1 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 { y ^= direction; }
+        direction ^= direction >> 1;
+        bits >>= 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Pseudo-random permutation of `[0, len)`,
+/// see Kensler, "Correlated Multi-Jittered Sampling", 2013.
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len { break; }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+#[inline]
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sequence::{Halton, Sequence, Sobol, Stratified, permute};
+
+    fn assert_stratified<S: Sequence>(sequence: S, samples: u64) {
+        let strata = f64::sqrt(samples as f64) as usize;
+        let mut cells = vec![0; strata * strata];
+        for index in 0..samples {
+            let [x, y] = sequence.sample_2d(3, 17, index, 1).unwrap();
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            cells[(y * strata as f64) as usize * strata + (x * strata as f64) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| count == 1), "{:?}", cells);
+    }
+
+    #[test]
+    fn test_sobol_is_stratified() {
+        assert_stratified(Sobol, 256);
+    }
+
+    #[test]
+    fn test_stratified_is_stratified() {
+        assert_stratified(Stratified::new(100), 100);
+    }
+
+    #[test]
+    fn test_halton_in_unit_square() {
+        for index in 0..1000 {
+            let [x, y] = Halton.sample_2d(1, 2, index, 5).unwrap();
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+        }
+    }
+
+    #[test]
+    fn test_permute_is_bijection() {
+        let len = 37;
+        let mut seen = vec![false; len as usize];
+        (0..len).for_each(|i| seen[permute(i, len, 12345) as usize] = true);
+        assert!(seen.iter().all(|&s| s));
+    }
+}
@@ -0,0 +1,67 @@
+use crate::vec::V3;
+
+/// Range of traced wavelengths, nanometers.
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 780.0;
+
+/// Mean of `rgb` over the traced range, keeps white light white when wavelengths are averaged.
+const MEAN_RGB: V3 = V3::new(0.4404433055265112, 0.2884639245495504, 0.2732961384716465);
+
+/// Wavelength picked uniformly by `u` with the weight of its color,
+/// averaging to white over many of them.
+pub fn sample_wavelength(u: f64) -> (f64, V3) {
+    let wavelength = MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH);
+    let rgb = rgb(wavelength);
+    (wavelength, V3::new(rgb.x / MEAN_RGB.x, rgb.y / MEAN_RGB.y, rgb.z / MEAN_RGB.z))
+}
+
+/// Linear sRGB of monochromatic light. Components outside of the gamut would be negative,
+/// they are clipped to zero, so that no wavelength takes light away from the image.
+pub fn rgb(wavelength: f64) -> V3 {
+    let c = xyz(wavelength);
+    V3::new(
+        f64::max(0.0, 3.2404542 * c.x - 1.5371385 * c.y - 0.4985314 * c.z),
+        f64::max(0.0, -0.9692660 * c.x + 1.8760108 * c.y + 0.0415560 * c.z),
+        f64::max(0.0, 0.0556434 * c.x - 0.2040259 * c.y + 1.0572252 * c.z),
+    )
+}
+
+/// CIE 1931 color matching functions, multi-lobe fit of Wyman, Sloan and Shirley (2013).
+pub fn xyz(wavelength: f64) -> V3 {
+    let g = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        f64::exp(-0.5 * t * t)
+    };
+    V3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::spectrum::sample_wavelength;
+    use crate::vec::V3;
+
+    #[test]
+    fn test_wavelengths_average_to_white() {
+        let count = 10_000;
+        let mut sum = V3::zeros();
+        for i in 0..count {
+            sum += sample_wavelength((i as f64 + 0.5) / count as f64).1;
+        }
+        assert!((sum / count as f64 - V3::ones()).length() < 1e-3);
+
+        let (red, weight) = sample_wavelength(0.7);
+        assert!(red > 600.0 && weight.x > weight.y && weight.x > weight.z);
+    }
+
+    #[test]
+    fn test_weights_are_not_negative() {
+        for i in 0..=1000 {
+            let (wavelength, weight) = sample_wavelength(i as f64 / 1000.0);
+            assert!(weight.x >= 0.0 && weight.y >= 0.0 && weight.z >= 0.0, "{} nm: {:?}", wavelength, weight);
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 pub use checker::*;
 pub use color::*;
 pub use perlin::*;
+pub use vertex_colors::*;
 
 use super::vec::V3;
 
@@ -12,11 +14,24 @@ pub mod color;
 pub mod checker;
 pub mod perlin;
 pub mod image;
+pub mod vertex_colors;
 
 pub trait Texture: Debug + Sync + Send {
     fn value(&self, u: f64, v: f64, point: V3) -> Color;
 }
 
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, point: V3) -> Color {
+        (**self).value(u, v, point)
+    }
+}
+
+impl<T: Texture + ?Sized> Texture for Box<T> {
+    fn value(&self, u: f64, v: f64, point: V3) -> Color {
+        (**self).value(u, v, point)
+    }
+}
+
 #[inline(always)]
 pub fn clamp(this: f64, lo: f64, hi: f64) -> f64 {
     if this < lo { lo } else if this > hi { hi } else { this }
@@ -0,0 +1,21 @@
+use crate::vec::V3;
+
+use super::{Color, Texture};
+
+/// Colors of triangle vertices, interpolated by barycentric `u` and `v` of the hit,
+/// so it works on triangles without texture coordinates.
+#[derive(Debug, Copy, Clone)]
+pub struct VertexColors([V3; 3]);
+
+impl VertexColors {
+    pub fn new(colors: [V3; 3]) -> VertexColors {
+        VertexColors(colors)
+    }
+}
+
+impl Texture for VertexColors {
+    fn value(&self, u: f64, v: f64, _: V3) -> Color {
+        let [c0, c1, c2] = self.0;
+        Color((1.0 - u - v) * c0 + u * c1 + v * c2)
+    }
+}
@@ -15,6 +15,7 @@ pub struct V3 {
     pub z: f64,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Axis { X, Y, Z }
 
 impl Axis {
@@ -39,6 +40,17 @@ impl Index<&Axis> for V3 {
     }
 }
 
+impl V3 {
+    /// Relative luminance of linear RGB (Rec. 709 coefficients).
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+}
+
 #[cfg(not(feature = "simd"))]
 impl V3 {
     pub const fn new(x: f64, y: f64, z: f64) -> V3 {